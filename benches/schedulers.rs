@@ -0,0 +1,81 @@
+//! Times the same scene under each `Scheduler`, run with `cargo bench --no-default-features`
+
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+use std::time::Instant;
+
+use cellular_physics::{
+    chunk::{Chunk, LEN},
+    chunk_map::ChunkMap,
+    encoding::Encoding16,
+    material::{MaterialId, Materials},
+    settings::{Scheduler, SimulationSettings},
+};
+
+/// Chunks on each side of the scene
+const SIZE: i32 = 12;
+const WARM_UP: u32 = 50;
+const SUB_STEPS: u32 = 300;
+
+/// Bands of sand and water over the top half and stone ledges below, the same for every seed
+fn scene(materials: &Materials) -> ChunkMap<Encoding16> {
+    let mut map = ChunkMap::with_seed(0);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            map.insert(ivec2(x, y), Chunk::EMPTY).unwrap();
+        }
+    }
+
+    let cells = SIZE * LEN;
+    for y in 0..cells {
+        for x in 0..cells {
+            let pos = ivec2(x, y);
+            if y >= cells / 2 {
+                let id = if (x / 16) % 2 == 0 {
+                    MaterialId::SAND
+                } else {
+                    MaterialId::WATER
+                };
+                map.set_dynamic(pos, id, materials);
+            } else if y % 96 == 48 && (x / 48) % 2 == 0 {
+                map.set_static(pos, MaterialId::STONE, materials);
+            }
+        }
+    }
+    map
+}
+
+fn main() {
+    ComputeTaskPool::get_or_init(TaskPool::new);
+    let materials = Materials::default();
+
+    println!("{SIZE}x{SIZE} chunks, {SUB_STEPS} sub steps after {WARM_UP} to warm up");
+    for (scheduler, deterministic) in [
+        (Scheduler::Linked, false),
+        (Scheduler::Linked, true),
+        (Scheduler::Checkerboard, false),
+    ] {
+        let settings = SimulationSettings {
+            scheduler,
+            deterministic,
+            ..default()
+        };
+        let mut map = scene(&materials);
+        for _ in 0..WARM_UP {
+            map.sub_step(&settings, &materials);
+        }
+
+        let start = Instant::now();
+        for _ in 0..SUB_STEPS {
+            map.sub_step(&settings, &materials);
+        }
+        let ms = start.elapsed().as_secs_f64() * 1000.0 / SUB_STEPS as f64;
+        let name = format!(
+            "{scheduler:?}{}",
+            if deterministic { " deterministic" } else { "" }
+        );
+        println!("{name:<24} {ms:>8.3} ms per sub step");
+    }
+}
@@ -0,0 +1,294 @@
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPoolBuilder},
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+    time::Instant,
+};
+
+use cellular_physics::{
+    cell::Cell,
+    chunk_map::ChunkMap,
+    encoding::Encoding32,
+    material::Materials,
+    settings::{Scheduler, SimulationSettings},
+    world_image::{Palette, export_png, import_png},
+};
+
+/// Same as the windowed app so saves are interchangeable
+type Encoding = Encoding32;
+
+const USAGE: &str = "\
+usage: cellular_physics-cli <scene.sav|scene.png> [options]
+
+Runs a scene without a window. A png scene is imported with the default materials' colours
+at the origin and runs with the default settings.
+
+options:
+  --steps <n>              fixed ticks to run [default: 100]
+  --sub-steps <n>          sub steps per tick, overrides the scene's
+  --scheduler <name>       linked or checkerboard, overrides the scene's
+  --deterministic          resolve edge moves in a fixed order
+  --threads <n>            compute threads [default: all cores]
+  --seed <n>               rng seed of png scenes [default: 0]
+  --stats <path|->         write a csv row of statistics every --stats-every ticks
+  --stats-every <n>        [default: 1]
+  --snapshots <dir>        export a png of the world every --snapshot-every ticks
+  --snapshot-every <n>     [default: 10]
+  --save <path>            save the world after the last tick
+";
+
+struct Args {
+    scene: PathBuf,
+    steps: u32,
+    sub_steps: Option<u32>,
+    scheduler: Option<Scheduler>,
+    deterministic: bool,
+    threads: Option<usize>,
+    seed: u64,
+    stats: Option<String>,
+    stats_every: u32,
+    snapshots: Option<PathBuf>,
+    snapshot_every: u32,
+    save: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    if env::args().any(|a| a == "-h" || a == "--help") {
+        print!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut scene = None;
+    let mut args = Args {
+        scene: PathBuf::new(),
+        steps: 100,
+        sub_steps: None,
+        scheduler: None,
+        deterministic: false,
+        threads: None,
+        seed: 0,
+        stats: None,
+        stats_every: 1,
+        snapshots: None,
+        snapshot_every: 10,
+        save: None,
+    };
+
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--steps" => args.steps = parse(&arg, value()?)?,
+            "--sub-steps" => args.sub_steps = Some(parse(&arg, value()?)?),
+            "--scheduler" => {
+                args.scheduler = Some(match value()?.as_str() {
+                    "linked" => Scheduler::Linked,
+                    "checkerboard" => Scheduler::Checkerboard,
+                    other => return Err(format!("unknown scheduler {other}")),
+                })
+            }
+            "--deterministic" => args.deterministic = true,
+            "--threads" => args.threads = Some(parse(&arg, value()?)?),
+            "--seed" => args.seed = parse(&arg, value()?)?,
+            "--stats" => args.stats = Some(value()?),
+            "--stats-every" => args.stats_every = parse(&arg, value()?)?,
+            "--snapshots" => args.snapshots = Some(value()?.into()),
+            "--snapshot-every" => args.snapshot_every = parse(&arg, value()?)?,
+            "--save" => args.save = Some(value()?.into()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ if scene.is_none() => scene = Some(arg.into()),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+
+    args.scene = scene.ok_or("missing scene")?;
+    Ok(args)
+}
+
+fn parse<T: FromStr>(name: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {value} for {name}"))
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let mut builder = TaskPoolBuilder::new();
+    if let Some(threads) = args.threads {
+        builder = builder.num_threads(threads);
+    }
+    ComputeTaskPool::get_or_init(|| builder.build());
+
+    let materials = Materials::default();
+    materials.assert_fits::<Encoding>();
+    let (mut map, mut settings) = load_scene(&args, &materials)?;
+    if let Some(sub_steps) = args.sub_steps {
+        settings.sub_steps = sub_steps;
+    }
+    if let Some(scheduler) = args.scheduler {
+        settings.scheduler = scheduler;
+    }
+    settings.deterministic |= args.deterministic;
+
+    let mut stats: Option<Box<dyn Write>> = match args.stats.as_deref() {
+        None => None,
+        Some("-") => Some(Box::new(io::stdout().lock())),
+        Some(path) => Some(Box::new(BufWriter::new(File::create(path)?))),
+    };
+    if let Some(stats) = &mut stats {
+        writeln!(stats, "{}", Stats::HEADER)?;
+    }
+    if let Some(dir) = &args.snapshots {
+        fs::create_dir_all(dir)?;
+    }
+
+    let start = Instant::now();
+    for tick in 1..=args.steps {
+        let tick_start = Instant::now();
+        for _ in 0..settings.sub_steps {
+            map.sub_step(&settings, &materials);
+        }
+        let tick_time = tick_start.elapsed();
+
+        if let Some(stats) = &mut stats
+            && tick.is_multiple_of(args.stats_every.max(1))
+        {
+            let row = Stats::of(&map);
+            writeln!(
+                stats,
+                "{tick},{row},{:.3}",
+                tick_time.as_secs_f64() * 1000.0
+            )?;
+        }
+
+        if let Some(dir) = &args.snapshots
+            && tick.is_multiple_of(args.snapshot_every.max(1))
+            && let Some(rect) = map.cell_bounds()
+        {
+            let file = File::create(dir.join(format!("tick_{tick:06}.png")))?;
+            export_png(&map, rect, &materials, BufWriter::new(file))?;
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+
+    if let Some(stats) = &mut stats {
+        stats.flush()?;
+    }
+    if let Some(path) = &args.save {
+        map.save(&settings, None, BufWriter::new(File::create(path)?))?;
+    }
+
+    let sub_steps = (args.steps * settings.sub_steps).max(1);
+    eprintln!(
+        "{} ticks in {elapsed:.1}ms, {:.3}ms per tick, {:.3}ms per sub step, world hash {:016x}",
+        args.steps,
+        elapsed / args.steps.max(1) as f64,
+        elapsed / sub_steps as f64,
+        map.world_hash(),
+    );
+    Ok(())
+}
+
+fn load_scene(
+    args: &Args,
+    materials: &Materials,
+) -> io::Result<(ChunkMap<Encoding>, SimulationSettings)> {
+    let file = BufReader::new(File::open(&args.scene)?);
+    match args.scene.extension().and_then(|e| e.to_str()) {
+        Some("png") => {
+            let mut map = ChunkMap::with_seed(args.seed);
+            let palette = Palette::from_materials(materials);
+            import_png(&mut map, file, IVec2::ZERO, &palette, materials)?;
+            Ok((map, SimulationSettings::default()))
+        }
+        Some("sav") => ChunkMap::load(file),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "scenes are .sav or .png files",
+        )),
+    }
+}
+
+/// A summary of the world after a tick
+struct Stats {
+    sub_steps: u32,
+    chunks: usize,
+    active_chunks: usize,
+    cells: usize,
+    dynamic_cells: usize,
+    mass: u64,
+    /// Sum of `mass * velocity²` in `1 / ONE` cells per sub step
+    kinetic_energy: u64,
+    absorbed_mass: u64,
+}
+
+impl Stats {
+    const HEADER: &str = "tick,sub_steps,chunks,active_chunks,cells,dynamic_cells,mass,kinetic_energy,absorbed_mass,tick_ms";
+
+    fn of(map: &ChunkMap<Encoding>) -> Self {
+        let mut stats = Self {
+            sub_steps: map.sub_steps(),
+            chunks: 0,
+            active_chunks: 0,
+            cells: 0,
+            dynamic_cells: 0,
+            mass: 0,
+            kinetic_energy: 0,
+            absorbed_mass: map.absorbed_mass(),
+        };
+        for (_, chunk) in map.chunks() {
+            stats.chunks += 1;
+            stats.active_chunks += chunk.is_active() as usize;
+        }
+        for (_, cell) in map.iter_some() {
+            stats.cells += 1;
+            if let Cell::Dynamic(cell) = cell {
+                stats.dynamic_cells += 1;
+                stats.mass += cell.mass as u64;
+                stats.kinetic_energy +=
+                    cell.mass as u64 * cell.velocity.as_ivec2().length_squared() as u64;
+            }
+        }
+        stats
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{}",
+            self.sub_steps,
+            self.chunks,
+            self.active_chunks,
+            self.cells,
+            self.dynamic_cells,
+            self.mass,
+            self.kinetic_energy,
+            self.absorbed_mass,
+        )
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::{platform::collections::HashSet, prelude::*};
+
+use crate::{chunk::LEN, chunk_map::ChunkMap, encoding::CellEncoding};
+
+/// Most cells `flood_fill` visits before giving up
+pub const MAX_FILL: usize = 1 << 18;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum BrushShape {
+    #[default]
+    Circle,
+    Square,
+}
+
+/// Cells within `radius` of `center`, a single cell at radius `0`
+pub fn stamp(center: IVec2, radius: i32, shape: BrushShape) -> impl Iterator<Item = IVec2> {
+    let r = radius.max(0);
+    (-r..=r)
+        .flat_map(move |y| (-r..=r).map(move |x| ivec2(x, y)))
+        // `+ r` rounds the circle out so small ones aren't just a cross
+        .filter(move |d| shape == BrushShape::Square || d.length_squared() <= r * r + r)
+        .map(move |d| center + d)
+}
+
+/// Cells on the line from `a` to `b` inclusive, by Bresenham's algorithm
+pub fn line(a: IVec2, b: IVec2) -> impl Iterator<Item = IVec2> {
+    let d = (b - a).abs() * ivec2(1, -1);
+    let step = (b - a).signum();
+    let mut pos = a;
+    let mut err = d.x + d.y;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let current = pos;
+        done = pos == b;
+        let e2 = 2 * err;
+        if e2 >= d.y {
+            err += d.y;
+            pos.x += step.x;
+        }
+        if e2 <= d.x {
+            err += d.x;
+            pos.y += step.y;
+        }
+        Some(current)
+    })
+}
+
+/// Every `stamp` along the line from `a` to `b`, each cell once in row order
+pub fn stroke(a: IVec2, b: IVec2, radius: i32, shape: BrushShape) -> Vec<IVec2> {
+    let cells = line(a, b)
+        .flat_map(|p| stamp(p, radius, shape))
+        .collect::<HashSet<_>>();
+    sorted(cells)
+}
+
+/// Cells of the rectangle with corners `a` and `b`, filled
+pub fn rect(a: IVec2, b: IVec2) -> impl Iterator<Item = IVec2> {
+    let r = IRect::from_corners(a, b);
+    (r.min.y..=r.max.y).flat_map(move |y| (r.min.x..=r.max.x).map(move |x| ivec2(x, y)))
+}
+
+/// The empty cells of loaded chunks connected to `start` through their sides in row order,
+/// `None` if `start` isn't one or there are more than `MAX_FILL`
+pub fn flood_fill<E: CellEncoding>(map: &ChunkMap<E>, start: IVec2) -> Option<Vec<IVec2>> {
+    let empty = |p: IVec2| map.contains(p.div_euclid(IVec2::splat(LEN))) && map.get(p).is_none();
+    if !empty(start) {
+        return None;
+    }
+
+    let mut seen = HashSet::from_iter([start]);
+    let mut stack = vec![start];
+    while let Some(p) = stack.pop() {
+        for n in [p - IVec2::X, p + IVec2::X, p - IVec2::Y, p + IVec2::Y] {
+            if empty(n) && seen.insert(n) {
+                if seen.len() > MAX_FILL {
+                    return None;
+                }
+                stack.push(n);
+            }
+        }
+    }
+    Some(sorted(seen))
+}
+
+/// In row order, so edits that draw from the `ChunkMap`'s rng stay deterministic
+fn sorted(cells: HashSet<IVec2>) -> Vec<IVec2> {
+    let mut cells = cells.into_iter().collect::<Vec<_>>();
+    cells.sort_unstable_by_key(|p| (p.y, p.x));
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        encoding::Encoding32,
+        material::{MaterialId, Materials},
+    };
+
+    #[test]
+    fn line_joins_its_ends_in_every_direction() {
+        let a = ivec2(3, -2);
+        for b in [
+            a,
+            ivec2(10, 1),
+            ivec2(4, 9),
+            ivec2(-5, 0),
+            ivec2(-1, -12),
+            ivec2(3, 7),
+            ivec2(9, -8),
+        ] {
+            let cells = line(a, b).collect::<Vec<_>>();
+            assert_eq!(cells.first(), Some(&a));
+            assert_eq!(cells.last(), Some(&b));
+            assert_eq!(
+                cells.len() as i32,
+                (b - a).abs().max_element() + 1,
+                "to {b}"
+            );
+            for pair in cells.windows(2) {
+                assert_eq!((pair[1] - pair[0]).abs().max_element(), 1, "to {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn flood_fill_stays_within_walls_and_loaded_chunks() {
+        let materials = Materials::default();
+        let mut map = ChunkMap::<Encoding32>::with_seed(0);
+        map.insert(IVec2::ZERO, Chunk::EMPTY).unwrap();
+        // a 5 by 4 room inside a ring of stone
+        for p in rect(ivec2(10, 10), ivec2(16, 15)) {
+            if p.x == 10 || p.x == 16 || p.y == 10 || p.y == 15 {
+                map.set_static(p, MaterialId::STONE, &materials);
+            }
+        }
+
+        let room = flood_fill(&map, ivec2(12, 12)).unwrap();
+        assert_eq!(room, rect(ivec2(11, 11), ivec2(15, 14)).collect::<Vec<_>>());
+        assert_eq!(flood_fill(&map, ivec2(10, 10)), None);
+        assert_eq!(flood_fill(&map, ivec2(-1, 0)), None);
+
+        let outside = flood_fill(&map, IVec2::ZERO).unwrap();
+        assert_eq!(outside.len() as i32, LEN * LEN - 7 * 6);
+    }
+}
@@ -1,49 +1,25 @@
 use bevy::{math::I8Vec2, prelude::*};
-use std::{
-    mem::ManuallyDrop,
-    sync::atomic::{AtomicU8, Ordering},
-};
-
-pub const MAX_SPEED: i8 = 3;
-
-const MAX_VELOCITY: I8Vec2 = I8Vec2::splat(MAX_SPEED);
-const MIN_VELOCITY: I8Vec2 = I8Vec2::splat(-MAX_SPEED);
-
-const MASK_3: u8 = 0b111;
-
-const X_SHIFT: u32 = 0;
-const X_MASK: u8 = MASK_3 << X_SHIFT;
+use std::sync::atomic::Ordering;
 
-const Y_SHIFT: u32 = 3;
-const Y_MASK: u8 = MASK_3 << Y_SHIFT;
-
-/// sign extension shift
-const I3_TO_I8_SHIFT: u32 = u8::BITS - 3;
-/// bit mask for relevent i8 bits
-const I8_TO_I3_MASK: u8 = MASK_3;
-
-const MASS_SHIFT: u32 = 6;
-const RESTITUTION_SHIFT: u32 = 4;
-
-/// -4 reprented as an i3
-const I3_NEG_4: u8 = 0x04;
-const INVALID_X: u8 = I3_NEG_4 << X_SHIFT;
-const INVALID_Y: u8 = I3_NEG_4 << Y_SHIFT;
-
-// const LOW_VALID_X: u8 = (!INVALID_X) & (1 << X_SHIFT);
-const LOW_VALID_Y: u8 = (!INVALID_Y) & (1 << Y_SHIFT);
+use crate::{
+    encoding::{CellEncoding, Encoding32, RESTITUTION_BITS},
+    material::{MaterialId, Materials},
+};
 
-const NONE_VALUE: u8 = INVALID_X | INVALID_Y;
-const STATIC_VALUE: u8 = INVALID_X;
-const SOME_STATIC_VALUE: u8 = STATIC_VALUE | LOW_VALID_Y;
+pub const MAX_RESTITUTION: i8 = (1 << RESTITUTION_BITS) - 1;
 
-const MAX_RESTITUTION: i8 = 15;
+/// Velocities and offsets are fixed point with this many fractional bits
+pub const FRACTION_BITS: u32 = 2;
+/// One cell per sub step
+pub const ONE: i8 = 1 << FRACTION_BITS;
 
+/// A `Cell` in the bit layout of `E`
 #[derive(Clone, Copy, PartialEq, Eq)]
-pub struct PackedCell(u8);
+#[repr(transparent)]
+pub struct PackedCell<E: CellEncoding = Encoding32>(E::Bits);
 
-impl PackedCell {
-    pub const NONE: Self = Self(NONE_VALUE);
+impl<E: CellEncoding> PackedCell<E> {
+    pub const NONE: Self = Self(E::NONE);
 
     pub fn unpack(self) -> Option<Cell> {
         self.is_some().then(|| {
@@ -51,36 +27,110 @@ impl PackedCell {
                 Cell::Dynamic(DynamicCell {
                     mass: self.mass(),
                     velocity: self.velocity(),
+                    offset: self.offset(),
+                    material: self.material(),
                 })
             } else {
                 Cell::Static(StaticCell {
                     restitution: self.restitution(),
+                    material: self.material(),
                 })
             }
         })
     }
 
+    /// `unpack` for the `n`th sub step,
+    /// recovers the offset from `n` if `E` doesn't store it
+    pub fn unpack_at(self, n: u32) -> Option<Cell> {
+        let cell = self.unpack();
+        if E::OFFSET_BITS != 0 {
+            return cell;
+        }
+        cell.map(|c| match c {
+            Cell::Dynamic(c) => Cell::Dynamic(DynamicCell {
+                offset: c.velocity.map(|v| phase(n, v)),
+                ..c
+            }),
+            c => c,
+        })
+    }
+
     pub fn is_some(self) -> bool {
-        self.0 != NONE_VALUE
+        self != Self::NONE
+    }
+
+    pub fn to_bits(self) -> u32 {
+        E::to_u32(self.0)
+    }
+
+    /// Any bits are a valid cell, though not necessarily one `pack` would make
+    pub fn from_bits(bits: u32) -> Self {
+        Self(E::from_u32(bits))
     }
 
     pub fn is_dynamic(self) -> bool {
-        self.0 & X_MASK != STATIC_VALUE
+        self.field(E::X_SHIFT, E::VELOCITY_BITS) != invalid_velocity::<E>()
+    }
+
+    fn field(self, shift: u32, bits: u32) -> u32 {
+        (E::to_u32(self.0) >> shift) & mask(bits)
+    }
+
+    /// sign extends the field by moving it to the top of an `i32` and back
+    fn signed_field(self, shift: u32, bits: u32) -> i8 {
+        ((E::to_u32(self.0) << (u32::BITS - shift - bits)) as i32 >> (u32::BITS - bits)) as i8
     }
 
     fn velocity(self) -> I8Vec2 {
-        let x = (((self.0 & X_MASK) << (I3_TO_I8_SHIFT - X_SHIFT)) as i8) >> I3_TO_I8_SHIFT;
-        let y = (((self.0 & Y_MASK) << (I3_TO_I8_SHIFT - Y_SHIFT)) as i8) >> I3_TO_I8_SHIFT;
-        I8Vec2::new(x, y)
+        I8Vec2::new(
+            self.signed_field(E::X_SHIFT, E::VELOCITY_BITS),
+            self.signed_field(E::Y_SHIFT, E::VELOCITY_BITS),
+        )
     }
 
     fn mass(self) -> i8 {
-        (self.0 >> MASS_SHIFT) as i8 + 1
+        self.field(E::MASS_SHIFT, E::MASS_BITS) as i8 + 1
+    }
+
+    fn offset(self) -> I8Vec2 {
+        I8Vec2::new(
+            self.field(E::OFFSET_X_SHIFT, E::OFFSET_BITS) as i8,
+            self.field(E::OFFSET_Y_SHIFT, E::OFFSET_BITS) as i8,
+        )
     }
 
     fn restitution(self) -> i8 {
-        (self.0 >> RESTITUTION_SHIFT) as i8
+        self.field(E::RESTITUTION_SHIFT, RESTITUTION_BITS) as i8
     }
+
+    fn material(self) -> MaterialId {
+        MaterialId(self.field(E::MATERIAL_SHIFT, E::MATERIAL_BITS) as u8)
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    (1 << bits) - 1
+}
+
+/// Offset of a cell that has moved at `v` for `n` sub steps
+fn phase(n: u32, v: i8) -> i8 {
+    // `ONE` is a power of 2 so only the low bits of `n` matter
+    ((n % ONE as u32) as i8 * v).rem_euclid(ONE)
+}
+
+/// The most negative velocity, used to tag static and `None` cells
+fn invalid_velocity<E: CellEncoding>() -> u32 {
+    1 << (E::VELOCITY_BITS - 1)
+}
+
+/// Ids that don't fit in `E` are rejected up front, see `Materials::assert_fits`
+fn material_bits<E: CellEncoding>(material: MaterialId) -> u32 {
+    debug_assert!(
+        (material.0 as usize) < E::MATERIALS,
+        "material {} doesn't fit the encoding",
+        material.0
+    );
+    (material.0 as u32 & mask(E::MATERIAL_BITS)) << E::MATERIAL_SHIFT
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -89,81 +139,167 @@ pub enum Cell {
     Dynamic(DynamicCell),
 }
 
+impl Cell {
+    pub fn material(self) -> MaterialId {
+        match self {
+            Self::Static(c) => c.material,
+            Self::Dynamic(c) => c.material,
+        }
+    }
+
+    pub fn pack<E: CellEncoding>(self) -> PackedCell<E> {
+        match self {
+            Self::Static(c) => c.pack(),
+            Self::Dynamic(c) => c.pack(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct StaticCell {
     pub restitution: i8,
+    pub material: MaterialId,
 }
 
 impl StaticCell {
-    pub fn pack(self) -> PackedCell {
-        debug_assert!((0..16).contains(&self.restitution));
+    /// Restitutions outside `0..=MAX_RESTITUTION` are clamped
+    pub fn pack<E: CellEncoding>(self) -> PackedCell<E> {
+        let restitution = self.restitution.clamp(0, MAX_RESTITUTION);
 
-        let restitution = (self.restitution as u8) << RESTITUTION_SHIFT;
+        // the low bit of y is set so that a static cell is never `NONE`
+        let tag = (invalid_velocity::<E>() << E::X_SHIFT) | (1 << E::Y_SHIFT);
+        let restitution = (restitution as u32) << E::RESTITUTION_SHIFT;
 
-        PackedCell(restitution | SOME_STATIC_VALUE)
+        PackedCell::from_bits(tag | restitution | material_bits::<E>(self.material))
     }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct DynamicCell {
     pub mass: i8,
+    /// In `1 / ONE` cells per sub step
     pub velocity: I8Vec2,
+    /// Position within the cell in `0..ONE`, a cell moves when this over or underflows
+    pub offset: I8Vec2,
+    pub material: MaterialId,
 }
 
 impl DynamicCell {
-    pub fn pack(self) -> PackedCell {
+    /// Masses outside `1..=E::MAX_MASS` and velocities past `E::MAX_SPEED` are clamped
+    pub fn pack<E: CellEncoding>(self) -> PackedCell<E> {
+        let mass = self.mass.clamp(1, E::MAX_MASS);
+        let velocity = self
+            .velocity
+            .clamp(I8Vec2::splat(-E::MAX_SPEED), I8Vec2::splat(E::MAX_SPEED));
+
         debug_assert!(
-            self.velocity.cmpge(MIN_VELOCITY).all() && self.velocity.cmple(MAX_VELOCITY).all()
+            self.offset.cmpge(I8Vec2::ZERO).all() && self.offset.cmplt(I8Vec2::splat(ONE)).all()
         );
-        debug_assert!((1..5).contains(&self.mass));
 
-        let mass = (self.mass as u8 - 1) << MASS_SHIFT;
-        let y = (self.velocity.y as u8 & I8_TO_I3_MASK) << Y_SHIFT;
-        let x = (self.velocity.x as u8 & I8_TO_I3_MASK) << X_SHIFT;
+        let mass = ((mass - 1) as u32) << E::MASS_SHIFT;
+        let y = (velocity.y as u32 & mask(E::VELOCITY_BITS)) << E::Y_SHIFT;
+        let x = (velocity.x as u32 & mask(E::VELOCITY_BITS)) << E::X_SHIFT;
+        let offset_y = (self.offset.y as u32 & mask(E::OFFSET_BITS)) << E::OFFSET_Y_SHIFT;
+        let offset_x = (self.offset.x as u32 & mask(E::OFFSET_BITS)) << E::OFFSET_X_SHIFT;
 
-        PackedCell(mass | y | x)
+        PackedCell::from_bits(
+            mass | y | x | offset_y | offset_x | material_bits::<E>(self.material),
+        )
     }
 
-    pub fn sub_step_delta(&self, n: u8) -> IVec2 {
-        debug_assert!((n as i8) < MAX_SPEED);
-        self.velocity
-            .map(|x| {
-                let remaining = x.abs() - n as i8;
-                if remaining > 0 { x.signum() } else { 0 }
-            })
+    /// Cells this cell moves by this sub step, at most one on each axis
+    pub fn sub_step_delta(&self) -> IVec2 {
+        (self.offset + self.velocity)
+            .map(|x| x.div_euclid(ONE))
             .as_ivec2()
     }
 
-    pub fn two_way_dynamic_collision(&mut self, other: &mut Self, delta: IVec2) {
+    /// Moves the offset along by a sub step
+    pub fn advance(&mut self) {
+        self.offset = (self.offset + self.velocity).map(|x| x.rem_euclid(ONE));
+    }
+
+    pub fn limit_speed(&mut self, max_speed: i8) {
+        self.velocity = self
+            .velocity
+            .clamp(I8Vec2::splat(-max_speed), I8Vec2::splat(max_speed));
+    }
+
+    pub fn two_way_dynamic_collision(
+        &mut self,
+        other: &mut Self,
+        delta: IVec2,
+        materials: &Materials,
+    ) {
         if delta.x != 0 {
-            self.dynamic_collision_x(other);
-            other.dynamic_collision_x(self);
+            self.dynamic_collision_x(other, materials);
+            other.dynamic_collision_x(self, materials);
         }
         if delta.y != 0 {
-            self.dynamic_collision_y(other);
-            other.dynamic_collision_y(self);
+            self.dynamic_collision_y(other, materials);
+            other.dynamic_collision_y(self, materials);
         }
     }
 
-    pub fn dynamic_collision(&mut self, other: &Self, delta: IVec2) {
+    pub fn dynamic_collision(&mut self, other: &Self, delta: IVec2, materials: &Materials) {
         if delta.x != 0 {
-            self.dynamic_collision_x(other);
+            self.dynamic_collision_x(other, materials);
         }
         if delta.y != 0 {
-            self.dynamic_collision_y(other);
+            self.dynamic_collision_y(other, materials);
         }
     }
 
-    pub fn dynamic_collision_x(&mut self, other: &Self) {
-        self.velocity.x =
-            dynamic_collision(self.velocity.x, self.mass, other.velocity.x, other.mass)
-                .clamp(-MAX_SPEED, MAX_SPEED);
+    pub fn dynamic_collision_x(&mut self, other: &Self, materials: &Materials) {
+        let elasticity = materials.elasticity(self.material, other.material);
+        self.velocity.x = dynamic_collision(
+            self.velocity.x,
+            self.mass,
+            other.velocity.x,
+            other.mass,
+            elasticity,
+        );
+    }
+
+    pub fn dynamic_collision_y(&mut self, other: &Self, materials: &Materials) {
+        let elasticity = materials.elasticity(self.material, other.material);
+        self.velocity.y = dynamic_collision(
+            self.velocity.y,
+            self.mass,
+            other.velocity.y,
+            other.mass,
+            elasticity,
+        );
     }
 
-    pub fn dynamic_collision_y(&mut self, other: &Self) {
-        self.velocity.y =
-            dynamic_collision(self.velocity.y, self.mass, other.velocity.y, other.mass)
-                .clamp(-MAX_SPEED, MAX_SPEED);
+    /// Comes to rest against `other`, the cell on the `side` axes of this one,
+    /// along the axes it presses into it no faster than `REST_SPEED`.
+    /// Run every sub step, not only the ones the offset carries the cell across,
+    /// so gravity can't build up into a bounce between cells at rest.
+    pub fn rest_against(&mut self, other: &Cell, side: IVec2, materials: &Materials) {
+        let other_velocity = match other {
+            Cell::Dynamic(c) => c.velocity,
+            Cell::Static(_) => I8Vec2::ZERO,
+        };
+        let closing = (self.velocity - other_velocity).as_ivec2() * side;
+        let slow = closing.cmpge(IVec2::ZERO) & closing.cmple(IVec2::splat(REST_SPEED.into()));
+        let delta = IVec2::select(slow, side, IVec2::ZERO);
+        match other {
+            Cell::Dynamic(other) => self.dynamic_collision(other, delta, materials),
+            Cell::Static(other) => self.static_collision(other, delta),
+        }
+        self.damp(delta);
+    }
+
+    /// Slows down by `REST_SPEED` along the axes of `delta`,
+    /// what every collision a cell is blocked by costs so bouncing cells come to rest
+    pub fn damp(&mut self, delta: IVec2) {
+        let damping = I8Vec2::select(
+            delta.cmpne(IVec2::ZERO),
+            I8Vec2::splat(REST_SPEED),
+            I8Vec2::ZERO,
+        );
+        self.velocity = self.velocity.signum() * (self.velocity.abs() - damping).max(I8Vec2::ZERO);
     }
 
     pub fn static_collision(&mut self, other: &StaticCell, delta: IVec2) {
@@ -176,46 +312,143 @@ impl DynamicCell {
     }
 
     pub fn static_collision_x(&mut self, other: &StaticCell) {
-        self.velocity.x =
-            static_collision(self.velocity.x, other.restitution).clamp(-MAX_SPEED, MAX_SPEED);
+        self.velocity.x = static_collision(self.velocity.x, other.restitution);
     }
 
     pub fn static_collision_y(&mut self, other: &StaticCell) {
-        self.velocity.y =
-            static_collision(self.velocity.y, other.restitution).clamp(-MAX_SPEED, MAX_SPEED);
+        self.velocity.y = static_collision(self.velocity.y, other.restitution);
     }
 
-    pub fn gravity(&mut self) {
-        self.velocity.y = (self.velocity.y - 1).max(-MAX_SPEED);
+    pub fn gravity(&mut self, materials: &Materials) {
+        let gravity = materials[self.material].gravity;
+        self.velocity.y = self.velocity.y.saturating_sub(gravity);
     }
 }
 
-fn dynamic_collision(v1: i8, m1: i8, v2: i8, m2: i8) -> i8 {
-    let v1 = v1 * 3 / 2;
-    let v2 = v2 * 3 / 2;
-    ((m1 - m2) * v1 + 2 * m2 * v2) / (m1 + m2)
+// Collisions are done in `i16` so they don't overflow for wide encodings,
+// the result is clamped to the encoding's `MAX_SPEED` when packed.
+//
+// Restitutions past 7 bounce no harder than 7, anything more would add speed with every bounce
+// and keep piles from ever settling. They still raise the average with less elastic materials.
+
+/// Collisions closing in this slowly or slower don't bounce,
+/// so cells resting on each other lose the velocity gravity gives them
+pub const REST_SPEED: i8 = 1;
+
+/// `e` is the restitution in sevenths
+fn dynamic_collision(v1: i8, m1: i8, v2: i8, m2: i8, e: i8) -> i8 {
+    let [v1, m1, v2, m2, e] = [v1, m1, v2, m2, e].map(i16::from);
+    if (v1 - v2).abs() <= REST_SPEED.into() {
+        // perfectly inelastic, rounded towards rest
+        return saturate((m1 * v1 + m2 * v2) / (m1 + m2));
+    }
+    let e = e.min(7);
+    let v1 = v1 * e / 7;
+    let v2 = v2 * e / 7;
+    saturate(((m1 - m2) * v1 + 2 * m2 * v2) / (m1 + m2))
 }
 
 fn static_collision(v: i8, r: i8) -> i8 {
-    -v * r / 7
+    if v.abs() <= REST_SPEED {
+        return 0;
+    }
+    saturate(-i16::from(v) * i16::from(r.min(7)) / 7)
 }
 
-pub struct AtomicPackedCell(AtomicU8);
+fn saturate(v: i16) -> i8 {
+    v.clamp(i8::MIN.into(), i8::MAX.into()) as i8
+}
+
+#[repr(transparent)]
+pub struct AtomicPackedCell<E: CellEncoding>(E::Atomic);
+
+impl<E: CellEncoding> AtomicPackedCell<E> {
+    /// Views a plain cell as an atomic one
+    ///
+    /// # Safety
+    /// `ptr` must be valid for `'a` and only accessed atomically while the view is used
+    pub unsafe fn from_ptr<'a>(ptr: *mut PackedCell<E>) -> &'a Self {
+        // Safety: atomics have the same layout as the `E::Bits` they wrap
+        unsafe { &*ptr.cast::<Self>() }
+    }
 
-impl AtomicPackedCell {
     pub fn update(
         &self,
         set_order: Ordering,
         fetch_order: Ordering,
-        mut f: impl FnMut(PackedCell) -> PackedCell,
+        mut f: impl FnMut(PackedCell<E>) -> PackedCell<E>,
     ) {
-        let _ = self
-            .0
-            .fetch_update(set_order, fetch_order, |c| Some(f(PackedCell(c)).0));
+        let _ = E::fetch_update(&self.0, set_order, fetch_order, |c| {
+            Some(f(PackedCell(c)).0)
+        });
     }
 }
 
-pub union MaybeAtomicPackedCell {
-    pub atomic: ManuallyDrop<AtomicPackedCell>,
-    pub plain: PackedCell,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{Encoding8, Encoding16, Encoding32};
+
+    /// Every value of every field packs and unpacks to itself
+    fn round_trips<E: CellEncoding>() {
+        let offsets = if E::OFFSET_BITS == 0 { 1 } else { ONE };
+        let materials = (0..E::MATERIALS).map(|m| MaterialId(m as u8));
+
+        for material in materials.clone() {
+            for restitution in 0..=MAX_RESTITUTION {
+                let cell = Cell::Static(StaticCell {
+                    restitution,
+                    material,
+                });
+                let p = cell.pack::<E>();
+                assert!(p.is_some() && !p.is_dynamic());
+                assert!(
+                    p.unpack() == Some(cell),
+                    "static {restitution} of {material:?}"
+                );
+            }
+        }
+
+        for material in materials {
+            for mass in 1..=E::MAX_MASS {
+                for vx in -E::MAX_SPEED..=E::MAX_SPEED {
+                    for vy in -E::MAX_SPEED..=E::MAX_SPEED {
+                        for ox in 0..offsets {
+                            for oy in 0..offsets {
+                                let cell = Cell::Dynamic(DynamicCell {
+                                    mass,
+                                    velocity: I8Vec2::new(vx, vy),
+                                    offset: I8Vec2::new(ox, oy),
+                                    material,
+                                });
+                                let p = cell.pack::<E>();
+                                assert!(p.is_some() && p.is_dynamic());
+                                assert!(
+                                    p.unpack() == Some(cell),
+                                    "mass {mass}, velocity ({vx}, {vy}), offset ({ox}, {oy}) of {material:?}"
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(PackedCell::<E>::NONE.unpack().is_none());
+    }
+
+    #[test]
+    fn encoding8_round_trips() {
+        round_trips::<Encoding8>();
+    }
+
+    #[test]
+    fn encoding16_round_trips() {
+        round_trips::<Encoding16>();
+    }
+
+    #[test]
+    fn encoding32_round_trips() {
+        round_trips::<Encoding32>();
+    }
 }
@@ -1,13 +1,21 @@
 use bevy::{math::I8Vec2, prelude::*};
 use enum_map::EnumMap;
 use ndshape::{ConstPow2Shape2u32, ConstShape};
-use rand::{Rng, rng};
-use std::{mem::transmute, ptr::NonNull, sync::atomic::Ordering};
+use rand::Rng;
+use std::{
+    io::{self, Read, Write},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
 
 use crate::{
     Dir::{self, *},
     OFFSETS,
-    cell::{Cell, DynamicCell, MaybeAtomicPackedCell, PackedCell, StaticCell},
+    cell::{AtomicPackedCell, Cell, DynamicCell, PackedCell, StaticCell},
+    encoding::{CellEncoding, Encoding32},
+    material::{Material, MaterialId, Materials},
+    save::{SaveReader, SaveWriter, invalid},
+    settings::SimulationSettings,
 };
 
 const BITS: u32 = 6;
@@ -17,8 +25,52 @@ const AREA: usize = LEN.pow(2) as usize;
 const MIN: i32 = 0;
 const MAX: i32 = LEN - 1;
 
+const FULL: IRect = IRect {
+    min: IVec2::splat(MIN),
+    max: IVec2::splat(MAX),
+};
+
+/// How far past a changed cell can change next sub step,
+/// a cell pulled into motion can push the one past it in the same sub step
+const DIRTY_MARGIN: i32 = 2;
+
 type Shape = ConstPow2Shape2u32<BITS, BITS>;
 
+/// What cells bounce off when there is no neighboring chunk
+const WORLD_EDGE: StaticCell = StaticCell {
+    restitution: 15,
+    material: MaterialId::STONE,
+};
+
+/// What happens to cells that move past the world edge
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Boundary {
+    /// They bounce off a static wall with this restitution in sevenths
+    Reflective(i8),
+    /// They're removed, see `ChunkMap::absorbed_mass`
+    Absorbing,
+    /// They come back in on the opposite side of these inclusive chunk positions,
+    /// which need to be at least 3 chunks across
+    Periodic(IRect),
+}
+
+impl Default for Boundary {
+    fn default() -> Self {
+        Self::Reflective(WORLD_EDGE.restitution)
+    }
+}
+
+impl Boundary {
+    /// Position of the chunk next to `k` in `dir`
+    pub fn neighbor(self, k: IVec2, dir: Dir) -> IVec2 {
+        let k = k + OFFSETS[dir];
+        match self {
+            Self::Periodic(rect) => rect.min + (k - rect.min).rem_euclid(rect.size() + 1),
+            _ => k,
+        }
+    }
+}
+
 use Bounds::*;
 enum Bounds {
     Within,
@@ -26,396 +78,751 @@ enum Bounds {
     Less,
 }
 
-pub struct Chunk {
-    read: [PackedCell; AREA],
-    /// Atomic when:
+pub struct Chunk<E: CellEncoding = Encoding32> {
+    read: [PackedCell<E>; AREA],
+    /// Accessed atomically by `Linked` when:
     /// 1. Parrallel neighbor access
     /// 2. If the cell `is_edge`
     /// 3. If the cell was `None` last `sub_step`
-    write: [MaybeAtomicPackedCell; AREA],
-    neighbors: EnumMap<Dir, Option<NonNull<Chunk>>>,
+    write: [PackedCell<E>; AREA],
+    neighbors: EnumMap<Dir, Option<NonNull<Chunk<E>>>>,
+    /// Moves into edge cells left for `resolve_deferred` in deterministic mode
+    deferred: Vec<EdgeMove>,
+    /// Sub steps since a cell last moved
+    idle: u32,
+    /// Set by neighbors moving cells at or into the shared edge, atomically by `Linked`
+    woken: bool,
+    /// Whether this sub step runs, see `update_active`
+    active: bool,
+    /// Cells that changed or kept moving last sub step, inclusive
+    dirty: IRect,
+    /// Cells written by this chunk this sub step
+    next_dirty: IRect,
+    /// Edge cells written by neighbors this sub step, grown atomically by `Linked`
+    foreign: IRect,
+    /// Cells that changed since `take_changed`, all of them in a new chunk
+    changed: IRect,
+    /// Missing neighbors cells tried to move into
+    missing: EnumMap<Dir, bool>,
+    /// Missing neighbors that will be back, allocated by the open world or reloaded by streaming.
+    /// Cells wait at the edge to cross into them instead of meeting the `Boundary`
+    walled: EnumMap<Dir, bool>,
+    /// Mass of the cells removed by an absorbing `Boundary` this sub step
+    absorbed: u32,
+}
+
+/// A cell at `i` moving by `delta` into the edge cell at `dst`
+struct EdgeMove {
+    i: usize,
+    dst: IVec2,
+    delta: IVec2,
+    cell: DynamicCell,
 }
 
 // Safety: only safe if used to parrallel execution of the same function on a chunk
-unsafe impl Send for Chunk {}
-unsafe impl Sync for Chunk {}
+unsafe impl<E: CellEncoding> Send for Chunk<E> {}
+unsafe impl<E: CellEncoding> Sync for Chunk<E> {}
 
-impl Chunk {
+impl<E: CellEncoding> Chunk<E> {
+    // every use is meant to be a fresh copy
+    #[allow(clippy::declare_interior_mutable_const)]
     pub const EMPTY: Self = Self {
         read: [PackedCell::NONE; AREA],
-        write: unsafe { transmute([PackedCell::NONE; AREA]) },
+        write: [PackedCell::NONE; AREA],
         neighbors: EnumMap::from_array([None; 8]),
+        deferred: Vec::new(),
+        idle: 0,
+        woken: false,
+        active: true,
+        dirty: IRect::EMPTY,
+        next_dirty: IRect::EMPTY,
+        foreign: IRect::EMPTY,
+        changed: FULL,
+        missing: EnumMap::from_array([false; 8]),
+        walled: EnumMap::from_array([false; 8]),
+        absorbed: 0,
     };
 
-    pub fn push_writes(&mut self) {
-        for (read, write) in self.read.iter_mut().zip(self.write.iter()) {
-            // Safety: Atomics are only nececcary when running functions on chunks that use its `neighbors`.
-            *read = unsafe { write.plain };
+    /// Decides whether this chunk runs this sub step,
+    /// it sleeps once no cell has moved for `sleep_after` sub steps
+    pub(crate) fn update_active(&mut self, sleep_after: u32) -> bool {
+        if std::mem::take(&mut self.woken) {
+            self.idle = 0;
+            // cells that were frozen mid move aren't in `dirty` anymore
+            self.dirty = FULL;
+        }
+        self.active = self.idle < sleep_after;
+        if !self.active {
+            self.dirty = IRect::EMPTY;
+        }
+        self.active
+    }
+
+    /// Active this sub step or written to by an active neighbor
+    pub(crate) fn has_writes(&self) -> bool {
+        self.active || self.woken
+    }
+
+    pub fn wake(&mut self) {
+        self.woken = true;
+    }
+
+    fn wake_neighbors(&self) {
+        for nn in self.neighbors.values().flatten() {
+            // Safety: `woken` is only written atomically while chunks run
+            unsafe { AtomicBool::from_ptr(&raw mut (*nn.as_ptr()).woken) }
+                .store(true, Ordering::Release);
+        }
+    }
+
+    /// Marks a cell as changed from outside the simulation
+    fn edited(&mut self, i: usize) {
+        self.woken = true;
+        if is_edge_or_ob(delinearize(i)) {
+            self.wake_neighbors();
+        }
+    }
+
+    pub(crate) fn push_writes(&mut self) {
+        let written = std::mem::replace(&mut self.next_dirty, IRect::EMPTY)
+            .union(std::mem::replace(&mut self.foreign, IRect::EMPTY));
+        for pos in cells(written) {
+            let i = linearize(pos.as_uvec2());
+            self.read[i] = self.write[i];
+        }
+        self.dirty = written;
+        self.changed = self.changed.union(written);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub(crate) fn take_missing(&mut self) -> EnumMap<Dir, bool> {
+        std::mem::take(&mut self.missing)
+    }
+
+    pub(crate) fn set_walled(&mut self, walled: EnumMap<Dir, bool>) {
+        self.walled = walled;
+    }
+
+    pub(crate) fn take_absorbed(&mut self) -> u32 {
+        std::mem::take(&mut self.absorbed)
+    }
+
+    /// Cells written since the last call, inclusive, for redrawing only what changed
+    pub fn take_changed(&mut self) -> IRect {
+        std::mem::replace(&mut self.changed, IRect::EMPTY)
+    }
+
+    /// The wall past the missing neighbor in `dir`, `None` if cells leave through it
+    /// or it's `walled` and they'll cross into it once it's back
+    fn past_edge(&self, dir: Dir, boundary: Boundary) -> Option<StaticCell> {
+        if self.walled[dir] {
+            return None;
+        }
+        match boundary {
+            Boundary::Reflective(restitution) => Some(StaticCell {
+                restitution,
+                material: WORLD_EDGE.material,
+            }),
+            Boundary::Absorbing => None,
+            // only reached through holes in the periodic world
+            Boundary::Periodic(_) => Some(WORLD_EDGE),
         }
     }
 
-    pub fn sub_step(&mut self, n: u8) {
-        for i in 0..AREA {
-            let Some(Cell::Dynamic(original_cell)) = self.read[i].unpack() else {
+    /// Runs a sub step on the center of `hood`
+    pub(crate) fn sub_step(
+        hood: &mut impl Neighborhood<E>,
+        n: u32,
+        boundary: Boundary,
+        settings: &SimulationSettings,
+        materials: &Materials,
+    ) {
+        let max_speed = settings.max_speed::<E>();
+        let mut moved = false;
+        let mut moved_at_edge = false;
+
+        for pos in cells(scan_rect(hood)) {
+            let i = linearize(pos.as_uvec2());
+            let Some(Cell::Dynamic(original_cell)) = hood.center().read[i].unpack_at(n) else {
                 continue;
             };
             let mut cell = original_cell;
-            let pos = delinearize(i);
 
             // pull collisions
             for (_, offset) in OFFSETS {
                 let adj_pos = pos + offset;
-                let adj_i = wrapping_linearize(adj_pos);
-
-                // Safety: `read` is shared
-                let nn_read = |nn: NonNull<Chunk>| unsafe { (*nn.as_ptr()).read[adj_i].unpack() };
-
-                let Some(Cell::Dynamic(adj_cell)) = (match bounds(adj_pos) {
-                    [Within, Within] => self.read[adj_i].unpack(),
-                    [Less, Within] => self.neighbors[Left].and_then(nn_read),
-                    [Greater, Within] => self.neighbors[Right].and_then(nn_read),
-                    [Within, Less] => self.neighbors[Down].and_then(nn_read),
-                    [Within, Greater] => self.neighbors[Up].and_then(nn_read),
-                    [Less, Less] => self.neighbors[DownLeft].and_then(nn_read),
-                    [Greater, Less] => self.neighbors[DownRight].and_then(nn_read),
-                    [Less, Greater] => self.neighbors[UpLeft].and_then(nn_read),
-                    [Greater, Greater] => self.neighbors[UpRight].and_then(nn_read),
-                }) else {
+
+                let Some(Cell::Dynamic(mut adj_cell)) = hood.read_at(adj_pos, n, boundary) else {
                     continue;
                 };
 
-                let sub_step_delta = adj_cell.sub_step_delta(n);
-
-                // if sub_step_delta.x != 0 && sub_step_delta.y != 0 {
-                //     let x_pos = adj_pos + ivec2(sub_step_delta.x, 0);
-                //     let x_i = wrapping_linearize(x_pos);
-
-                //     let nn_read = |nn: NonNull<Chunk>| unsafe { (*nn.as_ptr()).read[x_i].unpack() };
-
-                //     let x_cell = match bounds(x_pos) {
-                //         [Within, Within] => self.read[x_i].unpack(),
-                //         [Less, Within] => self.neighbors[Left].and_then(nn_read),
-                //         [Greater, Within] => self.neighbors[Right].and_then(nn_read),
-                //         [Within, Less] => self.neighbors[Down].and_then(nn_read),
-                //         [Within, Greater] => self.neighbors[Up].and_then(nn_read),
-                //         [Less, Less] => self.neighbors[DownLeft].and_then(nn_read),
-                //         [Greater, Less] => self.neighbors[DownRight].and_then(nn_read),
-                //         [Less, Greater] => self.neighbors[UpLeft].and_then(nn_read),
-                //         [Greater, Greater] => self.neighbors[UpRight].and_then(nn_read),
-                //     };
-
-                //     let y_pos = adj_pos + ivec2(0, sub_step_delta.y);
-                //     let y_i = wrapping_linearize(y_pos);
-
-                //     let nn_read = |nn: NonNull<Chunk>| unsafe { (*nn.as_ptr()).read[y_i].unpack() };
-                    
-                //     let y_cell = match bounds(y_pos) {
-                //         [Within, Within] => self.read[y_i].unpack(),
-                //         [Less, Within] => self.neighbors[Left].and_then(nn_read),
-                //         [Greater, Within] => self.neighbors[Right].and_then(nn_read),
-                //         [Within, Less] => self.neighbors[Down].and_then(nn_read),
-                //         [Within, Greater] => self.neighbors[Up].and_then(nn_read),
-                //         [Less, Less] => self.neighbors[DownLeft].and_then(nn_read),
-                //         [Greater, Less] => self.neighbors[DownRight].and_then(nn_read),
-                //         [Less, Greater] => self.neighbors[UpLeft].and_then(nn_read),
-                //         [Greater, Greater] => self.neighbors[UpRight].and_then(nn_read),
-                //     };
-
-                //     if !x_cell.is_some() && !y_cell.is_some() {
-                //         cell.dynamic_collision(&adj_cell, sub_step_delta);
-                //     }
-                // } else {
-                    let dst = pos + sub_step_delta;
-                    if dst == pos {
-                        cell.dynamic_collision(&adj_cell, sub_step_delta);
+                adj_cell.limit_speed(max_speed);
+                let sub_step_delta = adj_cell.sub_step_delta();
+
+                // this cell blocks one side of a diagonal move, which collides with it on that axis
+                if is_diagonal(sub_step_delta) {
+                    if adj_pos + ivec2(sub_step_delta.x, 0) == pos {
+                        cell.dynamic_collision_x(&adj_cell, materials);
+                        continue;
                     }
-                // }
+                    if adj_pos + ivec2(0, sub_step_delta.y) == pos {
+                        cell.dynamic_collision_y(&adj_cell, materials);
+                        continue;
+                    }
+                }
+
+                if adj_pos + sub_step_delta != pos {
+                    continue;
+                }
+
+                // a diagonal move past a blocked side never reaches `pos`
+                if is_diagonal(sub_step_delta)
+                    && hood
+                        .corner(adj_pos, sub_step_delta, n, boundary)
+                        .iter()
+                        .any(Option::is_some)
+                {
+                    continue;
+                }
+
+                cell.dynamic_collision(&adj_cell, sub_step_delta, materials);
+            }
+
+            // resting contact
+            let toward = cell.velocity.signum().as_ivec2();
+            for side in [ivec2(toward.x, 0), ivec2(0, toward.y)] {
+                if side != IVec2::ZERO
+                    && let Some(other) = hood.read_at(pos + side, n, boundary)
+                {
+                    cell.rest_against(&other, side, materials);
+                }
             }
 
             // push collision
-            let delta = cell.sub_step_delta(n);
+            cell.limit_speed(max_speed);
+            let mut delta = cell.sub_step_delta();
+            cell.advance();
+
+            // collide with each blocked side and carry on along the free one, if any
+            if is_diagonal(delta) {
+                let [x_cell, y_cell] = hood.corner(pos, delta, n, boundary);
+                if let Some(x_cell) = x_cell {
+                    match x_cell {
+                        Cell::Dynamic(x_cell) => cell.dynamic_collision_x(&x_cell, materials),
+                        Cell::Static(x_cell) => cell.static_collision_x(&x_cell),
+                    }
+                    cell.damp(IVec2::X);
+                    delta.x = 0;
+                }
+                if let Some(y_cell) = y_cell {
+                    match y_cell {
+                        Cell::Dynamic(y_cell) => cell.dynamic_collision_y(&y_cell, materials),
+                        Cell::Static(y_cell) => cell.static_collision_y(&y_cell),
+                    }
+                    cell.damp(IVec2::Y);
+                    delta.y = 0;
+                }
+            }
+
             if delta == IVec2::ZERO {
+                // at the top of its arc a cell with nothing under it still needs `gravity` to reach it
+                let gravity = materials[cell.material].gravity.signum() as i32;
+                let unsupported =
+                    gravity != 0 && hood.read_at(pos - ivec2(0, gravity), n, boundary).is_none();
+
+                let this = hood.center_mut();
                 if cell != original_cell {
-                    // `self.write[i].plain` can be freely written to b/c we know that no other threads will attempt to mutate a cell which was `Some` last frame
-                    self.write[i].plain = cell.pack();
+                    // `this.write[i]` can be freely written to b/c we know that no other threads will attempt to mutate a cell which was `Some` last frame
+                    this.write[i] = cell.pack();
+                }
+                // still moving within the cell, without an offset to store it may not have changed
+                if cell != original_cell || cell.velocity != I8Vec2::ZERO || unsupported {
+                    this.next_dirty = this.next_dirty.union_point(pos);
                 }
                 continue;
             }
 
-            // if delta.x != 0 && delta.y != 0 {
-            //     let x_pos = pos + ivec2(delta.x, 0);
-            //     let x_i = wrapping_linearize(x_pos);
-
-            //     let nn_read = |nn: NonNull<Chunk>| unsafe { (*nn.as_ptr()).read[x_i].unpack() };
-
-            //     let x_cell = match bounds(x_pos) {
-            //         [Within, Within] => self.read[x_i].unpack(),
-            //         [Less, Within] => self.neighbors[Left].and_then(nn_read),
-            //         [Greater, Within] => self.neighbors[Right].and_then(nn_read),
-            //         [Within, Less] => self.neighbors[Down].and_then(nn_read),
-            //         [Within, Greater] => self.neighbors[Up].and_then(nn_read),
-            //         [Less, Less] => self.neighbors[DownLeft].and_then(nn_read),
-            //         [Greater, Less] => self.neighbors[DownRight].and_then(nn_read),
-            //         [Less, Greater] => self.neighbors[UpLeft].and_then(nn_read),
-            //         [Greater, Greater] => self.neighbors[UpRight].and_then(nn_read),
-            //     };
-
-            //     let y_pos = pos + ivec2(0, delta.y);
-            //     let y_i = wrapping_linearize(y_pos);
-
-            //     let nn_read = |nn: NonNull<Chunk>| unsafe { (*nn.as_ptr()).read[y_i].unpack() };
-                
-            //     let y_cell = match bounds(y_pos) {
-            //         [Within, Within] => self.read[y_i].unpack(),
-            //         [Less, Within] => self.neighbors[Left].and_then(nn_read),
-            //         [Greater, Within] => self.neighbors[Right].and_then(nn_read),
-            //         [Within, Less] => self.neighbors[Down].and_then(nn_read),
-            //         [Within, Greater] => self.neighbors[Up].and_then(nn_read),
-            //         [Less, Less] => self.neighbors[DownLeft].and_then(nn_read),
-            //         [Greater, Less] => self.neighbors[DownRight].and_then(nn_read),
-            //         [Less, Greater] => self.neighbors[UpLeft].and_then(nn_read),
-            //         [Greater, Greater] => self.neighbors[UpRight].and_then(nn_read),
-            //     };
-                
-            //     match (x_cell, y_cell) {
-            //         (None, None) => {
-            //             let dst = pos + delta;
-            //             let dst_i = wrapping_linearize(dst);
-
-            //             fn ptr_to_ref<'a>(nn: NonNull<Chunk>) -> &'a Chunk {
-            //                 unsafe { &*nn.as_ptr() }
-            //             }
-
-            //             // Safety: Only used to read shared state or mutate shared atomics
-            //             let Some(chunk) = (match bounds(dst) {
-            //                 [Within, Within] => Some(&*self),
-            //                 [Less, Within] => self.neighbors[Left].map(ptr_to_ref),
-            //                 [Greater, Within] => self.neighbors[Right].map(ptr_to_ref),
-            //                 [Within, Less] => self.neighbors[Down].map(ptr_to_ref),
-            //                 [Within, Greater] => self.neighbors[Up].map(ptr_to_ref),
-            //                 [Less, Less] => self.neighbors[DownLeft].map(ptr_to_ref),
-            //                 [Greater, Less] => self.neighbors[DownRight].map(ptr_to_ref),
-            //                 [Less, Greater] => self.neighbors[UpLeft].map(ptr_to_ref),
-            //                 [Greater, Greater] => self.neighbors[UpRight].map(ptr_to_ref),
-            //             }) else {
-            //                 cell.static_collision(&StaticCell { restitution: 15 }, delta);
-            //                 self.write[i].plain = cell.pack();
-            //                 continue;
-            //             };
-
-            //             if let Some(dst_cell) = chunk.read[dst_i].unpack() {
-            //                 match dst_cell {
-            //                     Cell::Dynamic(dst_cell) => cell.dynamic_collision(&dst_cell, delta),
-            //                     Cell::Static(dst_cell) => cell.static_collision(&dst_cell, delta),
-            //                 }
-
-            //                 self.write[i].plain = cell.pack();
-            //             } else {
-            //                 if is_edge_or_ob(dst) {
-            //                     let mut replacement = PackedCell::NONE;
-
-            //                     // Safety: Atomic b/c `is_edge`
-            //                     let atomic = unsafe { &chunk.write[dst_i].atomic };
-
-            //                     atomic.update(Ordering::AcqRel, Ordering::Acquire, |dst_cell| {
-            //                         if let Some(dst_cell) = dst_cell.unpack() {
-            //                             if let Cell::Dynamic(mut dst_cell) = dst_cell {
-            //                                 cell.two_way_dynamic_collision(&mut dst_cell, delta);
-
-            //                                 replacement = cell.pack();
-
-            //                                 dst_cell.pack()
-            //                             } else {
-            //                                 unreachable!()
-            //                             }
-            //                         } else {
-            //                             replacement = PackedCell::NONE;
-
-            //                             cell.pack()
-            //                         }
-            //                     });
-
-            //                     self.write[i].plain = replacement;
-            //                 } else {
-            //                     // this branch only occurs when `chunk == self`
-
-            //                     // Safety: `!is_edge` no other threads can mutate `dst`
-            //                     let plain = unsafe { &mut self.write[dst_i].plain };
-
-            //                     if let Some(dst_cell) = plain.unpack() {
-            //                         if let Cell::Dynamic(mut dst_cell) = dst_cell {
-            //                             cell.two_way_dynamic_collision(&mut dst_cell, delta);
-
-            //                             *plain = dst_cell.pack();
-            //                             self.write[i].plain = cell.pack();
-            //                         } else {
-            //                             unreachable!()
-            //                         }
-            //                     } else {
-            //                         *plain = cell.pack();
-            //                         self.write[i].plain = PackedCell::NONE;
-            //                     };
-            //                 }
-            //             }
-            //         },
-            //         (x_cell, y_cell) => {
-            //             if let Some(x_cell) = x_cell {
-            //                 match x_cell {
-            //                     Cell::Dynamic(x_cell) => cell.dynamic_collision_x(&x_cell),
-            //                     Cell::Static(x_cell) => cell.static_collision_x(&x_cell),
-            //                 }
-            //             }
-            //             if let Some(y_cell) = y_cell {
-            //                 match y_cell {
-            //                     Cell::Dynamic(y_cell) => cell.dynamic_collision_y(&y_cell),
-            //                     Cell::Static(y_cell) => cell.static_collision_y(&y_cell),
-            //                 }
-            //             }
-
-            //             self.write[i].plain = cell.pack();
-            //         },
-            //     }
-            // } else {
-                let dst = pos + delta;
-                let dst_i = wrapping_linearize(dst);
-
-                fn ptr_to_ref<'a>(nn: NonNull<Chunk>) -> &'a Chunk {
-                    unsafe { &*nn.as_ptr() }
+            let this = hood.center_mut();
+            this.next_dirty = this.next_dirty.union_point(pos);
+
+            let dst = pos + delta;
+            let dst_i = wrapping_linearize(dst);
+
+            let Some(chunk) = hood.chunk_at(dst) else {
+                let dir = dir_of(dst).expect("the center always exists");
+                let this = hood.center_mut();
+                this.missing[dir] = true;
+                if this.walled[dir] {
+                    // held where it is, moving on into the neighbor once it's allocated
+                    cell.offset = original_cell.offset;
+                    this.write[i] = cell.pack();
+                } else if let Some(wall) = this.past_edge(dir, boundary) {
+                    cell.static_collision(&wall, delta);
+                    cell.damp(delta);
+                    this.write[i] = cell.pack();
+                } else {
+                    this.write[i] = PackedCell::NONE;
+                    this.absorbed += cell.mass as u32;
+                    moved = true;
                 }
+                continue;
+            };
 
-                // Safety: Only used to read shared state or mutate shared atomics
-                let Some(chunk) = (match bounds(dst) {
-                    [Within, Within] => Some(&*self),
-                    [Less, Within] => self.neighbors[Left].map(ptr_to_ref),
-                    [Greater, Within] => self.neighbors[Right].map(ptr_to_ref),
-                    [Within, Less] => self.neighbors[Down].map(ptr_to_ref),
-                    [Within, Greater] => self.neighbors[Up].map(ptr_to_ref),
-                    [Less, Less] => self.neighbors[DownLeft].map(ptr_to_ref),
-                    [Greater, Less] => self.neighbors[DownRight].map(ptr_to_ref),
-                    [Less, Greater] => self.neighbors[UpLeft].map(ptr_to_ref),
-                    [Greater, Greater] => self.neighbors[UpRight].map(ptr_to_ref),
-                }) else {
-                    cell.static_collision(&StaticCell { restitution: 15 }, delta);
-                    self.write[i].plain = cell.pack();
-                    continue;
-                };
+            if let Some(dst_cell) = chunk.read[dst_i].unpack() {
+                match dst_cell {
+                    Cell::Dynamic(dst_cell) => cell.dynamic_collision(&dst_cell, delta, materials),
+                    Cell::Static(dst_cell) => cell.static_collision(&dst_cell, delta),
+                }
+                cell.damp(delta);
 
-                if let Some(dst_cell) = chunk.read[dst_i].unpack() {
-                    match dst_cell {
-                        Cell::Dynamic(dst_cell) => cell.dynamic_collision(&dst_cell, delta),
-                        Cell::Static(dst_cell) => cell.static_collision(&dst_cell, delta),
-                    }
+                hood.center_mut().write[i] = cell.pack();
+            } else {
+                moved = true;
+                moved_at_edge |= is_edge_or_ob(pos) || is_edge_or_ob(dst);
 
-                    self.write[i].plain = cell.pack();
+                if is_edge_or_ob(dst) {
+                    hood.move_to_edge(i, dst, cell, delta, materials);
                 } else {
-                    if is_edge_or_ob(dst) {
-                        let mut replacement = PackedCell::NONE;
-
-                        // Safety: Atomic b/c `is_edge`
-                        let atomic = unsafe { &chunk.write[dst_i].atomic };
-
-                        atomic.update(Ordering::AcqRel, Ordering::Acquire, |dst_cell| {
-                            if let Some(dst_cell) = dst_cell.unpack() {
-                                if let Cell::Dynamic(mut dst_cell) = dst_cell {
-                                    cell.two_way_dynamic_collision(&mut dst_cell, delta);
-
-                                    replacement = cell.pack();
-
-                                    dst_cell.pack()
-                                } else {
-                                    unreachable!()
-                                }
-                            } else {
-                                replacement = PackedCell::NONE;
-
-                                cell.pack()
-                            }
-                        });
-
-                        self.write[i].plain = replacement;
-                    } else {
-                        // this branch only occurs when `chunk == self`
-
-                        // Safety: `!is_edge` no other threads can mutate `dst`
-                        let plain = unsafe { &mut self.write[dst_i].plain };
-
-                        if let Some(dst_cell) = plain.unpack() {
-                            if let Cell::Dynamic(mut dst_cell) = dst_cell {
-                                cell.two_way_dynamic_collision(&mut dst_cell, delta);
-
-                                *plain = dst_cell.pack();
-                                self.write[i].plain = cell.pack();
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            *plain = cell.pack();
-                            self.write[i].plain = PackedCell::NONE;
-                        };
-                    }
+                    // this branch only occurs when `chunk == this`
+                    let this = hood.center_mut();
+                    this.next_dirty = this.next_dirty.union_point(dst);
+
+                    // `!is_edge` so no other threads can mutate `dst`
+                    let plain = &mut this.write[dst_i];
+                    let [left, entered] = enter(cell, *plain, delta, materials);
+                    *plain = entered;
+                    this.write[i] = left;
                 }
-            // }
+            }
+        }
+
+        let this = hood.center_mut();
+        this.idle = if moved {
+            0
+        } else {
+            this.idle.saturating_add(1)
+        };
+        if moved_at_edge {
+            hood.wake_neighbors();
+        }
+    }
+
+    /// Applies the moves deferred by `sub_step`, in the order they were made
+    pub(crate) fn resolve_deferred(&mut self, materials: &Materials) {
+        let mut deferred = std::mem::take(&mut self.deferred);
+        let mut hood = Linked::new(self, false);
+        for EdgeMove {
+            i,
+            dst,
+            delta,
+            cell,
+        } in deferred.drain(..)
+        {
+            hood.move_to_edge(i, dst, cell, delta, materials);
         }
+        self.deferred = deferred;
     }
 
-    pub fn add_neighbor(&mut self, neighbor: &mut Self, dir: Dir) {
+    pub(crate) fn add_neighbor(&mut self, neighbor: &mut Self, dir: Dir) {
         self.neighbors[dir] = Some(NonNull::new(neighbor as *mut _).unwrap());
     }
 
-    pub fn remove_neighbor(&mut self, dir: Dir) {
+    pub(crate) fn remove_neighbor(&mut self, dir: Dir) {
         self.neighbors[dir] = None;
     }
 
-    pub fn iter_some(&self) -> impl Iterator<Item = IVec2> {
-        self.read.iter().enumerate().filter_map(|(i, c)| {
-            if let Some(_) = c.unpack() {
-                Some(delinearize(i))
-            } else {
-                None
-            }
-        })
+    /// Whether the neighbor in `dir` is linked
+    pub fn has_neighbor(&self, dir: Dir) -> bool {
+        self.neighbors[dir].is_some()
     }
 
-    pub fn set_dynamic(&mut self, cell_pos: UVec2) {
-        let i = linearize(cell_pos);
-        let mass = rng().random_range(1..=4);
-        let vel_x = rng().random_range(-3..=3);
-        let vel_y = rng().random_range(1..=3);
+    /// Raw bits of every cell, for hashing
+    pub fn bits(&self) -> impl Iterator<Item = u32> {
+        self.read.iter().map(|c| c.to_bits())
+    }
+
+    /// Length of `cell_bytes`
+    pub const SAVED_LEN: usize = AREA * size_of::<E::Bits>();
+
+    /// Every cell's bits, little endian
+    pub fn cell_bytes(&self) -> Vec<u8> {
+        let size = size_of::<E::Bits>();
+        let mut bytes = Vec::with_capacity(Self::SAVED_LEN);
+        for bits in self.bits() {
+            bytes.extend_from_slice(&bits.to_le_bytes()[..size]);
+        }
+        bytes
+    }
+
+    /// The chunk `cell_bytes` saved, woken so its cells carry on moving
+    pub fn from_cell_bytes(bytes: &[u8]) -> Self {
+        debug_assert_eq!(bytes.len(), Self::SAVED_LEN);
+        let size = size_of::<E::Bits>();
+        let mut chunk = Self::EMPTY;
+        for (i, c) in bytes.chunks_exact(size).take(AREA).enumerate() {
+            let mut bits = [0; 4];
+            bits[..size].copy_from_slice(c);
+            let p = PackedCell::from_bits(u32::from_le_bytes(bits));
+            chunk.read[i] = p;
+            chunk.write[i] = p;
+        }
+        chunk.woken = true;
+        chunk
+    }
+
+    /// Saves the cells and what decides which of them run next sub step,
+    /// so a loaded chunk carries on exactly as this one would
+    pub fn save(&self, w: &mut SaveWriter<impl Write>) -> io::Result<()> {
+        w.u32(self.idle)?;
+        w.bool(self.woken)?;
+        w.irect(self.dirty)?;
+        w.bytes(&self.cell_bytes())
+    }
+
+    pub fn load(r: &mut SaveReader<impl Read>) -> io::Result<Self> {
+        let idle = r.u32()?;
+        let woken = r.bool()?;
+        let dirty = r.irect()?;
+        if dirty != IRect::EMPTY && !(FULL.contains(dirty.min) && FULL.contains(dirty.max)) {
+            return Err(invalid("dirty cells outside of the chunk"));
+        }
+        let mut bytes = vec![0; Self::SAVED_LEN];
+        r.bytes(&mut bytes)?;
+
+        let mut chunk = Self::from_cell_bytes(&bytes);
+        chunk.idle = idle;
+        chunk.woken = woken;
+        chunk.dirty = dirty;
+        Ok(chunk)
+    }
+
+    pub fn iter_some(&self) -> impl Iterator<Item = (IVec2, Cell)> {
+        self.read
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| c.unpack().map(|c| (delinearize(i), c)))
+    }
+
+    pub fn set_dynamic(
+        &mut self,
+        cell_pos: UVec2,
+        id: MaterialId,
+        material: &Material,
+        rng: &mut impl Rng,
+    ) {
+        let mass = rng.random_range(material.mass.clone());
+        let vel_x = rng.random_range(-3..=3);
+        let vel_y = rng.random_range(1..=3);
         let velocity = I8Vec2::new(vel_x, vel_y);
-        let p = DynamicCell { mass, velocity }.pack();
-        self.write[i].plain = p;
-        self.read[i] = p;
+        let p = DynamicCell {
+            mass,
+            velocity,
+            offset: I8Vec2::ZERO,
+            material: id,
+        }
+        .pack();
+        self.set(cell_pos, p);
     }
 
-    pub fn set_static(&mut self, cell_pos: UVec2) {
-        let i = linearize(cell_pos);
-        let p = StaticCell { restitution: 15 }.pack();
-        self.write[i].plain = p;
-        self.read[i] = p;
+    pub fn set_static(&mut self, cell_pos: UVec2, id: MaterialId, material: &Material) {
+        let p = StaticCell {
+            restitution: material.elasticity,
+            material: id,
+        }
+        .pack();
+        self.set(cell_pos, p);
     }
 
     pub fn set_none(&mut self, cell_pos: UVec2) {
+        self.set(cell_pos, PackedCell::NONE);
+    }
+
+    /// Places `p` as is
+    pub fn set(&mut self, cell_pos: UVec2, p: PackedCell<E>) {
         let i = linearize(cell_pos);
-        let p = PackedCell::NONE;
-        self.write[i].plain = p;
+        self.write[i] = p;
         self.read[i] = p;
+        self.changed = self.changed.union_point(cell_pos.as_ivec2());
+        self.edited(i);
+    }
+
+    pub fn get(&self, cell_pos: UVec2) -> Option<Cell> {
+        self.read[linearize(cell_pos)].unpack()
+    }
+
+    pub fn get_packed(&self, cell_pos: UVec2) -> PackedCell<E> {
+        self.read[linearize(cell_pos)]
     }
 
-    pub fn gravity(&mut self) {
-        for (r, w) in self.read.iter_mut().zip(&mut self.write) {
-            if let Some(Cell::Dynamic(mut cell)) = r.unpack() {
-                cell.gravity();
+    /// Applies gravity to the cells near last sub step's changes,
+    /// any others are at rest or were woken along with the whole chunk
+    pub(crate) fn gravity(&mut self, max_speed: i8, materials: &Materials) {
+        for pos in cells(clamp_to_chunk(self.dirty.inflate(DIRTY_MARGIN))) {
+            let i = linearize(pos.as_uvec2());
+            if let Some(Cell::Dynamic(mut cell)) = self.read[i].unpack() {
+                cell.gravity(materials);
+                cell.limit_speed(max_speed);
                 let p = cell.pack();
-                *r = p;
-                w.plain = p;
+                if p != self.read[i] {
+                    self.dirty = self.dirty.union_point(pos);
+                    self.changed = self.changed.union_point(pos);
+                }
+                self.read[i] = p;
+                self.write[i] = p;
             }
         }
     }
 }
 
+/// Moves `cell` into `dst_cell`, which was empty last sub step,
+/// or collides with the cell that moved there first.
+/// Returns what is left behind and what ends up in `dst_cell`.
+fn enter<E: CellEncoding>(
+    mut cell: DynamicCell,
+    dst_cell: PackedCell<E>,
+    delta: IVec2,
+    materials: &Materials,
+) -> [PackedCell<E>; 2] {
+    match dst_cell.unpack() {
+        Some(Cell::Dynamic(mut dst_cell)) => {
+            cell.two_way_dynamic_collision(&mut dst_cell, delta, materials);
+            [cell.pack(), dst_cell.pack()]
+        }
+        Some(Cell::Static(_)) => unreachable!(),
+        None => [PackedCell::NONE, cell.pack()],
+    }
+}
+
+/// How `Chunk::sub_step` reaches the chunks around the one it runs on
+pub(crate) trait Neighborhood<E: CellEncoding> {
+    fn center(&self) -> &Chunk<E>;
+
+    fn center_mut(&mut self) -> &mut Chunk<E>;
+
+    fn neighbor(&self, dir: Dir) -> Option<&Chunk<E>>;
+
+    /// Moves the cell at `i` into the edge cell at `dst`, which was empty last sub step
+    fn move_to_edge(
+        &mut self,
+        i: usize,
+        dst: IVec2,
+        cell: DynamicCell,
+        delta: IVec2,
+        materials: &Materials,
+    );
+
+    fn wake_neighbors(&mut self);
+
+    /// The chunk `pos` falls in, `pos` may be up to a chunk out of bounds.
+    /// `None` if there is no neighbor there.
+    fn chunk_at(&self, pos: IVec2) -> Option<&Chunk<E>> {
+        match dir_of(pos) {
+            None => Some(self.center()),
+            Some(dir) => self.neighbor(dir),
+        }
+    }
+
+    /// Last sub step's cell at `pos`, past the world edge is the `boundary`'s wall
+    fn read_at(&self, pos: IVec2, n: u32, boundary: Boundary) -> Option<Cell> {
+        match self.chunk_at(pos) {
+            Some(chunk) => chunk.read[wrapping_linearize(pos)].unpack_at(n),
+            None => self
+                .center()
+                .past_edge(dir_of(pos).expect("the center always exists"), boundary)
+                .map(Cell::Static),
+        }
+    }
+
+    /// The `[x, y]` cells a diagonal move from `pos` by `delta` passes between
+    fn corner(&self, pos: IVec2, delta: IVec2, n: u32, boundary: Boundary) -> [Option<Cell>; 2] {
+        [ivec2(delta.x, 0), ivec2(0, delta.y)].map(|d| self.read_at(pos + d, n, boundary))
+    }
+}
+
+/// Cells that can change this sub step, the ones near last sub step's changes here or across an edge
+fn scan_rect<E: CellEncoding>(hood: &impl Neighborhood<E>) -> IRect {
+    let mut rect = hood.center().dirty.inflate(DIRTY_MARGIN);
+    for (dir, offset) in OFFSETS {
+        // `dirty` is only written outside of `sub_step`
+        let Some(dirty) = hood.neighbor(dir).map(|c| c.dirty) else {
+            continue;
+        };
+        if !is_empty(dirty) {
+            let offset = offset * LEN;
+            let dirty = IRect::from_corners(dirty.min + offset, dirty.max + offset);
+            rect = rect.union(dirty.inflate(DIRTY_MARGIN));
+        }
+    }
+    clamp_to_chunk(rect)
+}
+
+/// Whether an inclusive rect like `Chunk::take_changed`'s has no cells,
+/// `IRect::is_empty` treats `max` as exclusive
+pub fn is_empty(rect: IRect) -> bool {
+    rect.min.cmpgt(rect.max).any()
+}
+
+/// The cells of an inclusive rect within a chunk,
+/// `IRect::intersect` would turn an empty rect into a cell
+fn clamp_to_chunk(rect: IRect) -> IRect {
+    IRect {
+        min: rect.min.max(FULL.min),
+        max: rect.max.min(FULL.max),
+    }
+}
+
+/// A chunk reaching its neighbors through the pointers `ChunkMap::insert` links.
+/// Neighbors may run at the same time so edge cells are written atomically.
+pub(crate) struct Linked<'a, E: CellEncoding> {
+    chunk: &'a mut Chunk<E>,
+    /// Leave edge moves for `Chunk::resolve_deferred`
+    defer: bool,
+}
+
+impl<'a, E: CellEncoding> Linked<'a, E> {
+    pub fn new(chunk: &'a mut Chunk<E>, defer: bool) -> Self {
+        Self { chunk, defer }
+    }
+}
+
+impl<E: CellEncoding> Neighborhood<E> for Linked<'_, E> {
+    fn center(&self) -> &Chunk<E> {
+        self.chunk
+    }
+
+    fn center_mut(&mut self) -> &mut Chunk<E> {
+        self.chunk
+    }
+
+    fn neighbor(&self, dir: Dir) -> Option<&Chunk<E>> {
+        // Safety: Only used to read the cells and dirty rects, which aren't written while chunks run
+        self.chunk.neighbors[dir].map(|nn| unsafe { &*nn.as_ptr() })
+    }
+
+    fn move_to_edge(
+        &mut self,
+        i: usize,
+        dst: IVec2,
+        cell: DynamicCell,
+        delta: IVec2,
+        materials: &Materials,
+    ) {
+        if self.defer {
+            // the order neighbors write edge cells in depends on the threads
+            self.chunk.deferred.push(EdgeMove {
+                i,
+                dst,
+                delta,
+                cell,
+            });
+            return;
+        }
+
+        let chunk = match dir_of(dst) {
+            None => &raw mut *self.chunk,
+            Some(dir) => self.chunk.neighbors[dir]
+                .expect("edge moves only go into existing chunks")
+                .as_ptr(),
+        };
+
+        let mut replacement = PackedCell::NONE;
+
+        // Safety: Atomic b/c `is_edge`, `foreign` is only grown atomically while chunks run
+        let atomic = unsafe {
+            union_point_atomic(&raw mut (*chunk).foreign, wrapped(dst).as_ivec2());
+            AtomicPackedCell::from_ptr(&raw mut (*chunk).write[wrapping_linearize(dst)])
+        };
+
+        atomic.update(Ordering::AcqRel, Ordering::Acquire, |dst_cell| {
+            let [left, entered] = enter(cell, dst_cell, delta, materials);
+            replacement = left;
+            entered
+        });
+
+        self.chunk.write[i] = replacement;
+    }
+
+    fn wake_neighbors(&mut self) {
+        self.chunk.wake_neighbors();
+    }
+}
+
+/// Grows an `IRect` that other threads may be growing too
+///
+/// # Safety
+/// `rect` must be valid and only accessed atomically until every thread is done
+unsafe fn union_point_atomic(rect: *mut IRect, pos: IVec2) {
+    // Safety: forwarded to the caller
+    let (min, max) = unsafe {
+        (
+            [&raw mut (*rect).min.x, &raw mut (*rect).min.y],
+            [&raw mut (*rect).max.x, &raw mut (*rect).max.y],
+        )
+    };
+    for ((min, max), x) in min.into_iter().zip(max).zip(pos.to_array()) {
+        unsafe {
+            AtomicI32::from_ptr(min).fetch_min(x, Ordering::Relaxed);
+            AtomicI32::from_ptr(max).fetch_max(x, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A chunk with exclusive borrows of its neighbors, handed out by `ChunkMap`'s checkerboard scheduler.
+/// Nothing else touches these chunks while it runs, so it moves cells with plain reads and writes.
+pub(crate) struct Borrowed<'a, E: CellEncoding> {
+    pub center: &'a mut Chunk<E>,
+    pub neighbors: EnumMap<Dir, Option<&'a mut Chunk<E>>>,
+}
+
+impl<E: CellEncoding> Neighborhood<E> for Borrowed<'_, E> {
+    fn center(&self) -> &Chunk<E> {
+        self.center
+    }
+
+    fn center_mut(&mut self) -> &mut Chunk<E> {
+        self.center
+    }
+
+    fn neighbor(&self, dir: Dir) -> Option<&Chunk<E>> {
+        self.neighbors[dir].as_deref()
+    }
+
+    fn move_to_edge(
+        &mut self,
+        i: usize,
+        dst: IVec2,
+        cell: DynamicCell,
+        delta: IVec2,
+        materials: &Materials,
+    ) {
+        let chunk = match dir_of(dst) {
+            None => &mut *self.center,
+            Some(dir) => self.neighbors[dir]
+                .as_deref_mut()
+                .expect("edge moves only go into existing chunks"),
+        };
+        chunk.foreign = chunk.foreign.union_point(wrapped(dst).as_ivec2());
+
+        let plain = &mut chunk.write[wrapping_linearize(dst)];
+        let [left, entered] = enter(cell, *plain, delta, materials);
+        *plain = entered;
+
+        self.center.write[i] = left;
+    }
+
+    fn wake_neighbors(&mut self) {
+        for chunk in self.neighbors.values_mut().flatten() {
+            chunk.wake();
+        }
+    }
+}
+
 fn bounds(pos: IVec2) -> [Bounds; 2] {
     pos.to_array().map(|x| {
-        if x >= MIN && x <= MAX {
+        if (MIN..=MAX).contains(&x) {
             Within
         } else if x < MIN {
             Less
@@ -425,6 +832,26 @@ fn bounds(pos: IVec2) -> [Bounds; 2] {
     })
 }
 
+/// Which neighbor `pos` falls in, `None` if it's within the chunk
+fn dir_of(pos: IVec2) -> Option<Dir> {
+    match bounds(pos) {
+        [Within, Within] => None,
+        [Less, Within] => Some(Left),
+        [Greater, Within] => Some(Right),
+        [Within, Less] => Some(Down),
+        [Within, Greater] => Some(Up),
+        [Less, Less] => Some(DownLeft),
+        [Greater, Less] => Some(DownRight),
+        [Less, Greater] => Some(UpLeft),
+        [Greater, Greater] => Some(UpRight),
+    }
+}
+
+/// Every cell in `rect` row by row, the same order as their index
+fn cells(rect: IRect) -> impl Iterator<Item = IVec2> {
+    (rect.min.y..=rect.max.y).flat_map(move |y| (rect.min.x..=rect.max.x).map(move |x| ivec2(x, y)))
+}
+
 fn is_edge_or_ob(pos: IVec2) -> bool {
     pos.cmple(IVec2::splat(MIN)).any() || pos.cmpge(IVec2::splat(MAX)).any()
 }
@@ -448,3 +875,210 @@ fn delinearize(i: usize) -> IVec2 {
 fn is_diagonal(delta: IVec2) -> bool {
     delta.x != 0 && delta.y != 0
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+    use super::*;
+    use crate::{
+        cell::{ONE, REST_SPEED},
+        chunk_map::ChunkMap,
+    };
+
+    const STONE: Cell = Cell::Static(StaticCell {
+        restitution: 7,
+        material: MaterialId::STONE,
+    });
+
+    fn at_rest(mass: i8) -> DynamicCell {
+        DynamicCell {
+            mass,
+            velocity: I8Vec2::ZERO,
+            offset: I8Vec2::ZERO,
+            material: MaterialId::SAND,
+        }
+    }
+
+    fn one_chunk() -> ChunkMap {
+        ComputeTaskPool::get_or_init(TaskPool::new);
+        let mut map = ChunkMap::with_seed(0);
+        map.insert(IVec2::ZERO, Chunk::EMPTY).unwrap();
+        map
+    }
+
+    /// A single chunk with a cell at `(10, 10)` moving up and right, and `cells` around it
+    fn diagonal(cells: &[(IVec2, Cell)]) -> ChunkMap {
+        let mut map = one_chunk();
+        let mover = DynamicCell {
+            velocity: I8Vec2::splat(ONE),
+            ..at_rest(2)
+        };
+        map.set(ivec2(10, 10), Some(Cell::Dynamic(mover)));
+        for &(pos, cell) in cells {
+            map.set(pos, Some(cell));
+        }
+        map
+    }
+
+    /// Runs a sub step without gravity
+    fn step(map: &mut ChunkMap) {
+        let settings = SimulationSettings {
+            gravity_interval: u32::MAX,
+            ..default()
+        };
+        map.sub_step(&settings, &Materials::default());
+    }
+
+    fn dynamic(map: &ChunkMap, pos: IVec2) -> DynamicCell {
+        match map.get(pos) {
+            Some(Cell::Dynamic(cell)) => cell,
+            _ => panic!("no dynamic cell at {pos}"),
+        }
+    }
+
+    #[test]
+    fn diagonal_blocked_on_both_sides_bounces_back() {
+        let mut map = diagonal(&[
+            (ivec2(11, 10), STONE),
+            (ivec2(10, 11), STONE),
+            (ivec2(11, 11), Cell::Dynamic(at_rest(2))),
+        ]);
+        step(&mut map);
+
+        // bounced back and damped by the collision
+        assert_eq!(
+            dynamic(&map, ivec2(10, 10)).velocity,
+            I8Vec2::splat(-ONE + REST_SPEED)
+        );
+        // nothing passes between the two stones, not even a collision
+        assert_eq!(dynamic(&map, ivec2(11, 11)).velocity, I8Vec2::ZERO);
+    }
+
+    #[test]
+    fn diagonal_blocked_on_one_side_slides_along_it() {
+        let mut map = diagonal(&[(ivec2(11, 10), STONE)]);
+        step(&mut map);
+
+        assert!(map.get(ivec2(10, 10)).is_none());
+        assert!(map.get(ivec2(11, 11)).is_none());
+        assert_eq!(
+            dynamic(&map, ivec2(10, 11)).velocity,
+            I8Vec2::new(-ONE + REST_SPEED, ONE)
+        );
+    }
+
+    #[test]
+    fn diagonal_pushes_a_dynamic_blocker() {
+        let mut map = diagonal(&[(ivec2(11, 10), Cell::Dynamic(at_rest(2)))]);
+        step(&mut map);
+
+        let mover = dynamic(&map, ivec2(10, 11));
+        let blocker = dynamic(&map, ivec2(11, 10));
+        assert!(mover.velocity.x < ONE);
+        assert_eq!(mover.velocity.y, ONE);
+        assert!(blocker.velocity.x > 0);
+        assert_eq!(blocker.velocity.y, 0);
+    }
+
+    #[test]
+    fn thrown_up_cell_falls_back_down() {
+        let materials = Materials::default();
+        let settings = SimulationSettings::default();
+        for speed in 1..=3 {
+            let mut map = one_chunk();
+            let thrown = DynamicCell {
+                velocity: I8Vec2::new(0, speed),
+                ..at_rest(3)
+            };
+            map.set(ivec2(32, 24), Some(Cell::Dynamic(thrown)));
+
+            for _ in 0..300 {
+                map.sub_step(&settings, &materials);
+            }
+
+            let (_, chunk) = map.chunks().next().unwrap();
+            let cells = chunk.iter_some().collect::<Vec<_>>();
+            assert_eq!(cells.len(), 1);
+            assert_eq!(
+                cells[0].0.y, 0,
+                "thrown at {speed} it hangs at {}",
+                cells[0].0
+            );
+        }
+    }
+
+    #[test]
+    fn crosses_into_allocated_chunk() {
+        let mut map = one_chunk();
+        map.set_open_world(Some(IRect::new(-1, 0, 0, 0)));
+        let mover = DynamicCell {
+            velocity: I8Vec2::new(-ONE, 0),
+            ..at_rest(2)
+        };
+        map.set(ivec2(1, 10), Some(Cell::Dynamic(mover)));
+
+        // into the edge cell, held there while the chunk past it is allocated, then across
+        for _ in 0..3 {
+            step(&mut map);
+        }
+
+        assert!(map.contains(ivec2(-1, 0)));
+        assert_eq!(dynamic(&map, ivec2(-1, 10)).velocity, mover.velocity);
+    }
+
+    #[test]
+    fn slow_cell_moves_once_every_one_sub_steps() {
+        for start in 0..ONE as u32 {
+            let mut map = one_chunk();
+            for _ in 0..start {
+                step(&mut map);
+            }
+            let slow = DynamicCell {
+                velocity: I8Vec2::new(1, 0),
+                ..at_rest(2)
+            };
+            map.set(ivec2(10, 10), Some(Cell::Dynamic(slow)));
+
+            let mut moves = Vec::new();
+            for n in 1..=4 * ONE as u32 {
+                step(&mut map);
+                let (pos, _) = map.iter_some().next().unwrap();
+                if pos.x != 10 + moves.len() as i32 {
+                    moves.push(n);
+                }
+            }
+            let every_one = [1, 2, 3, 4].map(|k| k * ONE as u32);
+            assert_eq!(moves, every_one, "placed after {start} sub steps");
+        }
+    }
+
+    #[test]
+    fn settled_pile_sleeps() {
+        let materials = Materials::default();
+        let settings = SimulationSettings::default();
+        let mut map = one_chunk();
+        for y in 20..40 {
+            for x in 8..56 {
+                let id = if y < 30 {
+                    MaterialId::SAND
+                } else {
+                    MaterialId::WATER
+                };
+                map.set_dynamic(ivec2(x, y), id, &materials);
+            }
+        }
+
+        for _ in 0..300 {
+            map.sub_step(&settings, &materials);
+        }
+
+        let (_, chunk) = map.chunks().next().unwrap();
+        assert!(!chunk.is_active());
+        for (pos, cell) in chunk.iter_some() {
+            if let Cell::Dynamic(cell) = cell {
+                assert_eq!(cell.velocity, I8Vec2::ZERO, "{pos} is still moving");
+            }
+        }
+    }
+}
@@ -1,51 +1,432 @@
 use bevy::{
-    platform::collections::HashMap,
+    platform::collections::{HashMap, HashSet},
     prelude::*,
     tasks::{ComputeTaskPool, ParallelSliceMut},
 };
 use enum_map::{Enum, EnumMap};
-use std::array::from_fn;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+    array::from_fn,
+    fmt,
+    io::{self, Read, Write},
+};
 
 use crate::{
     Dir, OFFSETS,
-    chunk::{Chunk, LEN},
+    cell::{Cell, PackedCell},
+    chunk::{Borrowed, Boundary, Chunk, LEN, Linked},
+    encoding::{CellEncoding, Encoding32},
+    material::{MaterialId, Materials},
+    region::RegionStore,
+    save::{SaveReader, SaveWriter, invalid},
+    settings::{Scheduler, SimulationSettings},
 };
 
-#[derive(Resource, Default)]
-pub struct ChunkMap {
-    map: HashMap<IVec2, Chunk>,
+#[derive(Resource)]
+pub struct ChunkMap<E: CellEncoding = Encoding32> {
+    /// Boxed so the neighbor pointers chunks hold stay valid when the map reallocates
+    map: HashMap<IVec2, Box<Chunk<E>>>,
+    /// Sub steps run so far, drives the gravity cadence and the offsets of encodings that don't store them
+    sub_steps: u32,
+    /// Source of every random choice in the simulation
+    rng: ChaCha8Rng,
+    /// Chunk positions allocated on demand when cells cross into them or are edited,
+    /// `None` keeps the world to the chunks inserted by hand
+    open_world: Option<IRect>,
+    boundary: Boundary,
+    /// Mass of every cell an absorbing `boundary` has removed
+    absorbed_mass: u64,
+    /// Chunks `stream` saved, kept from being allocated again until they're loaded
+    unloaded: HashSet<IVec2>,
+}
+
+/// Everything needed to resume a `ChunkMap`'s random stream
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RngState {
+    pub seed: [u8; 32],
+    pub word_pos: u128,
+}
+
+/// Why a `ChunkMap` refused a `Boundary` or a chunk
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BoundaryError {
+    /// A periodic world less than 3 chunks across, a chunk would be its own neighbor
+    TooSmall,
+    /// A chunk position outside of the periodic world
+    OutsideWorld(IVec2),
+}
+
+impl fmt::Display for BoundaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooSmall => write!(f, "periodic worlds need to be at least 3 chunks across"),
+            Self::OutsideWorld(k) => write!(f, "chunk {k} is outside of the periodic world"),
+        }
+    }
 }
 
-impl ChunkMap {
-    pub fn sub_step(&mut self, n: u8) {
-        let mut vec = self.map.values_mut().collect::<Vec<_>>();
+impl std::error::Error for BoundaryError {}
+
+impl<E: CellEncoding> Default for ChunkMap<E> {
+    /// Seeded from the OS, use `with_seed` for reproducible spawns
+    fn default() -> Self {
+        Self::with_seed(rand::rng().next_u64())
+    }
+}
+
+impl<E: CellEncoding> ChunkMap<E> {
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            map: HashMap::default(),
+            sub_steps: 0,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            open_world: None,
+            boundary: Boundary::default(),
+            absorbed_mass: 0,
+            unloaded: HashSet::default(),
+        }
+    }
+
+    /// `bounds` are inclusive chunk positions
+    pub fn set_open_world(&mut self, bounds: Option<IRect>) {
+        self.open_world = bounds;
+        for k in self.sorted_keys() {
+            let walled = self.walled_dirs(k);
+            self.map.get_mut(&k).unwrap().set_walled(walled);
+        }
+    }
+
+    /// Fails and keeps the old boundary if `boundary` is a periodic world that's too small
+    /// or doesn't contain every chunk
+    pub fn set_boundary(&mut self, boundary: Boundary) -> Result<(), BoundaryError> {
+        if let Boundary::Periodic(rect) = boundary {
+            // any less and a chunk would be its own neighbor
+            if !rect.size().cmpge(IVec2::splat(2)).all() {
+                return Err(BoundaryError::TooSmall);
+            }
+            if let Some(k) = self.sorted_keys().into_iter().find(|k| !rect.contains(*k)) {
+                return Err(BoundaryError::OutsideWorld(k));
+            }
+        }
+        self.boundary = boundary;
+
+        // neighbors across the world edge change
+        for (k, chunk) in std::mem::take(&mut self.map) {
+            self.link(k, *chunk, true);
+        }
+        Ok(())
+    }
+
+    pub fn absorbed_mass(&self) -> u64 {
+        self.absorbed_mass
+    }
 
-        if n == 0 {
+    /// Sub steps run so far
+    pub fn sub_steps(&self) -> u32 {
+        self.sub_steps
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec2, &Chunk<E>)> {
+        self.map.iter().map(|(k, c)| (*k, &**c))
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = (IVec2, &mut Chunk<E>)> {
+        self.map.iter_mut().map(|(k, c)| (*k, &mut **c))
+    }
+
+    pub fn rng_state(&self) -> RngState {
+        RngState {
+            seed: self.rng.get_seed(),
+            word_pos: self.rng.get_word_pos(),
+        }
+    }
+
+    pub fn set_rng_state(&mut self, state: RngState) {
+        self.rng = ChaCha8Rng::from_seed(state.seed);
+        self.rng.set_word_pos(state.word_pos);
+    }
+
+    pub fn sub_step(&mut self, settings: &SimulationSettings, materials: &Materials) {
+        self.sub_steps = self.sub_steps.wrapping_add(1);
+        let n = self.sub_steps;
+        let max_speed = settings.max_speed::<E>();
+
+        let mut vec = self
+            .map
+            .values_mut()
+            .filter_map(|c| c.update_active(settings.sleep_after).then_some(c))
+            .collect::<Vec<_>>();
+
+        if settings.is_gravity_sub_step(n) {
             vec.par_splat_map_mut(ComputeTaskPool::get(), None, |_, slice| {
                 for c in slice {
-                    c.gravity();
+                    c.gravity(max_speed, materials);
                 }
             });
         }
 
-        vec.par_splat_map_mut(ComputeTaskPool::get(), None, |_, slice| {
-            for c in slice {
-                c.sub_step(n);
+        match settings.scheduler {
+            Scheduler::Linked => {
+                let defer = settings.deterministic;
+                vec.par_splat_map_mut(ComputeTaskPool::get(), None, |_, slice| {
+                    for c in slice {
+                        Chunk::sub_step(
+                            &mut Linked::new(c, defer),
+                            n,
+                            self.boundary,
+                            settings,
+                            materials,
+                        );
+                    }
+                });
+
+                if defer {
+                    for k in self.sorted_keys() {
+                        self.map.get_mut(&k).unwrap().resolve_deferred(materials);
+                    }
+                }
             }
-        });
+            Scheduler::Checkerboard => self.checkerboard_sub_step(n, settings, materials),
+        }
+
+        let mut vec = self
+            .map
+            .values_mut()
+            .filter(|c| c.has_writes())
+            .collect::<Vec<_>>();
         vec.par_splat_map_mut(ComputeTaskPool::get(), None, |_, slice| {
             for c in slice {
                 c.push_writes();
             }
         });
+
+        if self.boundary == Boundary::Absorbing {
+            self.absorbed_mass += self
+                .map
+                .values_mut()
+                .map(|c| c.take_absorbed() as u64)
+                .sum::<u64>();
+        }
+
+        if self.open_world.is_some() {
+            self.allocate_missing();
+        }
     }
 
-    pub fn insert(&mut self, k: IVec2, v: Chunk) {
-        self.map.insert(k, v);
+    /// Allocates the chunks cells tried to move into this sub step,
+    /// they bounced off the world edge but can cross next sub step
+    fn allocate_missing(&mut self) {
+        let mut missing = Vec::new();
+        for (k, chunk) in &mut self.map {
+            for (dir, wanted) in chunk.take_missing() {
+                let k = self.boundary.neighbor(*k, dir);
+                if wanted && !missing.contains(&k) {
+                    missing.push(k);
+                }
+            }
+        }
+
+        for k in missing {
+            if self.can_allocate(k) {
+                self.link(k, Chunk::EMPTY, true);
+            }
+        }
+    }
+
+    /// Allocates the chunk `cell_pos` falls in if it's missing and within the open world
+    fn allocate_for_edit(&mut self, cell_pos: IVec2) {
+        let chunk_pos = cell_pos.div_euclid(IVec2::splat(LEN));
+        if self.can_allocate(chunk_pos) && !self.map.contains_key(&chunk_pos) {
+            self.link(chunk_pos, Chunk::EMPTY, true);
+        }
+    }
+
+    fn can_allocate(&self, k: IVec2) -> bool {
+        self.in_world(k)
+            && self.open_world.is_some_and(|b| b.contains(k))
+            && !self.unloaded.contains(&k)
+    }
+
+    /// Whether a chunk can go at `k`, anywhere unless the world is periodic
+    pub fn in_world(&self, k: IVec2) -> bool {
+        match self.boundary {
+            Boundary::Periodic(rect) => rect.contains(k),
+            _ => true,
+        }
+    }
+
+    /// Missing neighbors of `k` that aren't the world edge
+    fn walled_dirs(&self, k: IVec2) -> EnumMap<Dir, bool> {
+        OFFSETS.map(|dir, _| {
+            let k = self.boundary.neighbor(k, dir);
+            self.can_allocate(k) || self.unloaded.contains(&k)
+        })
+    }
+
+    /// Saves the chunks further than `radius` chunks from every `focus` to `store`
+    /// and loads the ones this map saved within it
+    pub fn stream(
+        &mut self,
+        focus: &[IVec2],
+        radius: i32,
+        store: &mut RegionStore,
+    ) -> io::Result<()> {
+        let in_range = |k: IVec2| focus.iter().any(|f| (k - *f).abs().max_element() <= radius);
+
+        for k in self.sorted_keys() {
+            if in_range(k) {
+                continue;
+            }
+            store.save(k, &self.map[&k])?;
+            self.remove(k);
+            self.unloaded.insert(k);
+
+            for dir in (0..Dir::LENGTH).map(Dir::from_usize) {
+                let k = self.boundary.neighbor(k, dir);
+                let walled = self.walled_dirs(k);
+                if let Some(chunk) = self.map.get_mut(&k) {
+                    chunk.set_walled(walled);
+                }
+            }
+        }
+
+        for f in focus {
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    let k = f + ivec2(x, y);
+                    // other worlds saved to the same store aren't this one's
+                    if !self.unloaded.contains(&k) || !self.in_world(k) {
+                        continue;
+                    }
+                    if let Some(chunk) = store.load(k)? {
+                        self.unloaded.remove(&k);
+                        self.link(k, chunk, true);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs active chunks in 9 phases, one per position in a repeating 3x3 pattern.
+    /// Chunks in a phase are 3 apart so their neighborhoods never overlap
+    /// and can all be borrowed at once, except across a periodic edge
+    /// where the overlapping ones wait for another round of the phase.
+    fn checkerboard_sub_step(
+        &mut self,
+        n: u32,
+        settings: &SimulationSettings,
+        materials: &Materials,
+    ) {
+        let boundary = self.boundary;
+        let mut chunks = self
+            .map
+            .iter_mut()
+            .map(|(k, c)| (*k, &mut **c))
+            .collect::<HashMap<_, _>>();
+
+        for phase in 0..9 {
+            let phase = ivec2(phase % 3, phase / 3);
+            let mut pending = chunks
+                .iter()
+                .filter(|(k, c)| k.rem_euclid(IVec2::splat(3)) == phase && c.is_active())
+                .map(|(k, _)| *k)
+                .collect::<Vec<_>>();
+            // keeps phases with many chunks in the same order every run
+            pending.sort_unstable_by_key(|k| (k.y, k.x));
+
+            while !pending.is_empty() {
+                let mut centers = Vec::with_capacity(pending.len());
+                let mut later = Vec::new();
+                let mut taken = HashSet::new();
+                for k in pending {
+                    let hood_ks = OFFSETS.map(|dir, _| boundary.neighbor(k, dir));
+                    if taken.contains(&k) || hood_ks.values().any(|k| taken.contains(k)) {
+                        later.push(k);
+                        continue;
+                    }
+                    taken.insert(k);
+                    taken.extend(hood_ks.values().copied());
+                    centers.push(k);
+                }
+
+                let mut hoods = Vec::with_capacity(centers.len());
+                for &k in &centers {
+                    hoods.push(Borrowed {
+                        center: chunks.remove(&k).unwrap(),
+                        neighbors: OFFSETS.map(|dir, _| chunks.remove(&boundary.neighbor(k, dir))),
+                    });
+                }
+
+                hoods.par_splat_map_mut(ComputeTaskPool::get(), None, |_, slice| {
+                    for hood in slice {
+                        Chunk::sub_step(hood, n, boundary, settings, materials);
+                    }
+                });
+
+                for (k, hood) in centers.into_iter().zip(hoods) {
+                    chunks.insert(k, hood.center);
+                    for (dir, chunk) in hood.neighbors {
+                        if let Some(chunk) = chunk {
+                            chunks.insert(boundary.neighbor(k, dir), chunk);
+                        }
+                    }
+                }
+
+                pending = later;
+            }
+        }
+    }
+
+    /// FNV-1a hash of every cell and the sub step counter,
+    /// equal for equal worlds regardless of platform or insertion order
+    pub fn world_hash(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const PRIME: u64 = 0x100000001b3;
+
+        let mut hash = OFFSET_BASIS;
+        let mut write = |x: u32| {
+            for byte in x.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(PRIME);
+            }
+        };
+
+        write(self.sub_steps);
+        for k in self.sorted_keys() {
+            write(k.x as u32);
+            write(k.y as u32);
+            self.map[&k].bits().for_each(&mut write);
+        }
+        hash
+    }
+
+    /// Chunk positions row by row, bottom to top
+    fn sorted_keys(&self) -> Vec<IVec2> {
+        let mut keys = self.map.keys().copied().collect::<Vec<_>>();
+        keys.sort_unstable_by_key(|k| (k.y, k.x));
+        keys
+    }
+
+    /// Fails without inserting anything if `k` is outside of the periodic world
+    pub fn insert(&mut self, k: IVec2, v: Chunk<E>) -> Result<(), BoundaryError> {
+        if !self.in_world(k) {
+            return Err(BoundaryError::OutsideWorld(k));
+        }
+        self.link(k, v, true);
+        Ok(())
+    }
+
+    /// Inserts `v` and links it with its neighbors, waking them if `wake`.
+    /// `k` has to be `in_world`
+    fn link(&mut self, k: IVec2, v: Chunk<E>, wake: bool) {
+        debug_assert!(self.in_world(k), "chunk outside of the periodic world");
+        let walled = self.walled_dirs(k);
+        self.map.insert(k, Box::new(v));
 
         let ks: [_; 9] = from_fn(|i| {
             if i < Dir::LENGTH {
-                OFFSETS[Dir::from_usize(i)] + k
+                self.boundary.neighbor(k, Dir::from_usize(i))
             } else {
                 k
             }
@@ -55,20 +436,26 @@ impl ChunkMap {
         // get_many_mut is the shittiest function ever
         let [a, b, c, d, e, f, g, h, middle] = self.map.get_many_mut(ref_ks);
 
-        let chunk_opts: EnumMap<Dir, Option<&mut Chunk>> =
-            EnumMap::from_array([a, b, c, d, e, f, g, h]);
+        let chunk_opts = EnumMap::<Dir, _>::from_array([a, b, c, d, e, f, g, h]);
         let middle = middle.unwrap();
+        middle.set_walled(walled);
 
         for (dir, chunk_opt) in chunk_opts {
             if let Some(chunk) = chunk_opt {
                 middle.add_neighbor(chunk, dir);
                 chunk.add_neighbor(middle, dir.inverse());
+                if wake {
+                    // cells resting against the world edge may now fall through
+                    chunk.wake();
+                }
+            } else {
+                middle.remove_neighbor(dir);
             }
         }
     }
 
     pub fn remove(&mut self, k: IVec2) {
-        let ks = OFFSETS.map(|_, o| k + o);
+        let ks = OFFSETS.map(|dir, _| self.boundary.neighbor(k, dir));
         let ref_ks = from_fn(|i| &ks[Dir::from_usize(i)]);
 
         let chunk_opts: EnumMap<Dir, _> = EnumMap::from_array(self.map.get_many_mut(ref_ks));
@@ -76,31 +463,143 @@ impl ChunkMap {
         for (dir, chunk_opt) in chunk_opts {
             if let Some(chunk) = chunk_opt {
                 chunk.remove_neighbor(dir.inverse());
+                chunk.wake();
             }
         }
 
         self.map.remove(&k);
     }
 
-    pub fn iter_some(&self) -> impl Iterator<Item = IVec2> {
+    /// Writes the world and the `settings` it runs with to `w`, see `save` for the format.
+    /// Chunks `stream` saved to `store` are read back and written along with the rest,
+    /// so the save doesn't depend on the store. Fails if there are any and `store` is `None`.
+    pub fn save(
+        &self,
+        settings: &SimulationSettings,
+        store: Option<&mut RegionStore>,
+        w: impl Write,
+    ) -> io::Result<()> {
+        let mut streamed = Vec::new();
+        if !self.unloaded.is_empty() {
+            let store = store
+                .ok_or_else(|| io::Error::other("chunks are streamed out to a region store"))?;
+            let mut unloaded = self.unloaded.iter().copied().collect::<Vec<_>>();
+            unloaded.sort_unstable_by_key(|k| (k.y, k.x));
+            for k in unloaded {
+                let chunk = store.load::<E>(k)?.ok_or_else(|| {
+                    invalid("streamed out chunk is missing from the region store")
+                })?;
+                streamed.push((k, chunk));
+            }
+        }
+
+        let mut w = SaveWriter::new::<E>(w)?;
+        w.settings(settings)?;
+
+        w.u32(self.sub_steps)?;
+        let rng = self.rng_state();
+        w.bytes(&rng.seed)?;
+        w.u128(rng.word_pos)?;
+
+        w.boundary(self.boundary)?;
+        w.bool(self.open_world.is_some())?;
+        if let Some(bounds) = self.open_world {
+            w.irect(bounds)?;
+        }
+        w.u64(self.absorbed_mass)?;
+
+        let keys = self.sorted_keys();
+        w.u32((keys.len() + streamed.len()) as u32)?;
+        for k in keys {
+            w.ivec2(k)?;
+            self.map[&k].save(&mut w)?;
+        }
+        for (k, chunk) in &streamed {
+            w.ivec2(*k)?;
+            chunk.save(&mut w)?;
+        }
+
+        w.finish()?.flush()
+    }
+
+    /// Reads a world `save` wrote, along with the settings it ran with.
+    /// It carries on exactly as the saved one would have.
+    pub fn load(r: impl Read) -> io::Result<(Self, SimulationSettings)> {
+        let mut r = SaveReader::new::<E>(r)?;
+        let settings = r.settings()?;
+
+        let mut map = Self::with_seed(0);
+        map.sub_steps = r.u32()?;
+        let mut seed = [0; 32];
+        r.bytes(&mut seed)?;
+        map.set_rng_state(RngState {
+            seed,
+            word_pos: r.u128()?,
+        });
+
+        map.boundary = r.boundary()?;
+        if let Boundary::Periodic(rect) = map.boundary
+            && !rect.size().cmpge(IVec2::splat(2)).all()
+        {
+            return Err(invalid("periodic world is too small"));
+        }
+        map.open_world = if r.bool()? { Some(r.irect()?) } else { None };
+        map.absorbed_mass = r.u64()?;
+
+        for _ in 0..r.u32()? {
+            let k = r.ivec2()?;
+            if map.map.contains_key(&k) || !map.in_world(k) {
+                return Err(invalid("chunk out of place"));
+            }
+            // saved chunks are already woken if their neighbors were inserted since
+            map.link(k, Chunk::load(&mut r)?, false);
+        }
+
+        Ok((map, settings))
+    }
+
+    pub fn contains(&self, k: IVec2) -> bool {
+        self.map.contains_key(&k)
+    }
+
+    /// Inclusive cell positions of the smallest rect around every chunk
+    pub fn cell_bounds(&self) -> Option<IRect> {
+        let mut keys = self.map.keys();
+        let first = *keys.next()?;
+        let rect = keys.fold(IRect::from_corners(first, first), |r, k| r.union_point(*k));
+        Some(IRect {
+            min: rect.min * LEN,
+            max: rect.max * LEN + (LEN - 1),
+        })
+    }
+
+    pub fn get(&self, cell_pos: IVec2) -> Option<Cell> {
+        let chunk_pos = cell_pos.div_euclid(IVec2::splat(LEN));
+        let local_cell_pos = cell_pos.rem_euclid(IVec2::splat(LEN)).as_uvec2();
+        self.map.get(&chunk_pos)?.get(local_cell_pos)
+    }
+
+    pub fn iter_some(&self) -> impl Iterator<Item = (IVec2, Cell)> {
         self.map
             .iter()
-            .flat_map(|(p, c)| c.iter_some().map(|s| s + (*p * LEN)))
+            .flat_map(|(p, c)| c.iter_some().map(|(s, c)| (s + (*p * LEN), c)))
     }
 
-    pub fn set_dynamic(&mut self, cell_pos: IVec2) {
+    pub fn set_dynamic(&mut self, cell_pos: IVec2, id: MaterialId, materials: &Materials) {
+        self.allocate_for_edit(cell_pos);
         let chunk_pos = cell_pos.div_euclid(IVec2::splat(LEN));
         if let Some(chunk) = self.map.get_mut(&chunk_pos) {
             let local_cell_pos = cell_pos.rem_euclid(IVec2::splat(LEN)).as_uvec2();
-            chunk.set_dynamic(local_cell_pos)
+            chunk.set_dynamic(local_cell_pos, id, &materials[id], &mut self.rng)
         }
     }
 
-    pub fn set_static(&mut self, cell_pos: IVec2) {
+    pub fn set_static(&mut self, cell_pos: IVec2, id: MaterialId, materials: &Materials) {
+        self.allocate_for_edit(cell_pos);
         let chunk_pos = cell_pos.div_euclid(IVec2::splat(LEN));
         if let Some(chunk) = self.map.get_mut(&chunk_pos) {
             let local_cell_pos = cell_pos.rem_euclid(IVec2::splat(LEN)).as_uvec2();
-            chunk.set_static(local_cell_pos)
+            chunk.set_static(local_cell_pos, id, &materials[id])
         }
     }
 
@@ -111,4 +610,282 @@ impl ChunkMap {
             chunk.set_none(local_cell_pos)
         }
     }
+
+    /// Places `cell` as is, `None` clears it
+    pub fn set(&mut self, cell_pos: IVec2, cell: Option<Cell>) {
+        self.set_packed(cell_pos, cell.map_or(PackedCell::NONE, Cell::pack));
+    }
+
+    /// `None` if the cell's chunk isn't loaded
+    pub fn get_packed(&self, cell_pos: IVec2) -> Option<PackedCell<E>> {
+        let chunk_pos = cell_pos.div_euclid(IVec2::splat(LEN));
+        let local_cell_pos = cell_pos.rem_euclid(IVec2::splat(LEN)).as_uvec2();
+        Some(self.map.get(&chunk_pos)?.get_packed(local_cell_pos))
+    }
+
+    pub fn set_packed(&mut self, cell_pos: IVec2, p: PackedCell<E>) {
+        if p.is_some() {
+            self.allocate_for_edit(cell_pos);
+        }
+        let chunk_pos = cell_pos.div_euclid(IVec2::splat(LEN));
+        if let Some(chunk) = self.map.get_mut(&chunk_pos) {
+            let local_cell_pos = cell_pos.rem_euclid(IVec2::splat(LEN)).as_uvec2();
+            chunk.set(local_cell_pos, p)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        math::I8Vec2,
+        tasks::{ComputeTaskPool, TaskPool},
+    };
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::{
+        cell::DynamicCell,
+        encoding::{Encoding8, Encoding16, Encoding32},
+        material::Material,
+    };
+
+    /// 3 by 3 chunks with a block of water falling on sand
+    fn world(materials: &Materials) -> ChunkMap<Encoding16> {
+        ComputeTaskPool::get_or_init(TaskPool::new);
+        let mut map = ChunkMap::with_seed(7);
+        for y in 0..3 {
+            for x in 0..3 {
+                map.insert(ivec2(x, y), Chunk::EMPTY).unwrap();
+            }
+        }
+        for y in 20..60 {
+            for x in 40..150 {
+                let id = if y < 40 {
+                    MaterialId::SAND
+                } else {
+                    MaterialId::WATER
+                };
+                map.set_dynamic(ivec2(x, y), id, materials);
+            }
+        }
+        map
+    }
+
+    fn step(map: &mut ChunkMap<Encoding16>, n: u32, settings: &SimulationSettings) {
+        let materials = Materials::default();
+        for _ in 0..n {
+            map.sub_step(settings, &materials);
+        }
+    }
+
+    fn saved(map: &ChunkMap<Encoding16>, settings: &SimulationSettings) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        map.save(settings, None, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn assert_invalid(result: io::Result<impl Sized>) {
+        let Err(e) = result else {
+            panic!("loaded an invalid save");
+        };
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn round_trip() {
+        for scheduler in [Scheduler::Linked, Scheduler::Checkerboard] {
+            let settings = SimulationSettings {
+                scheduler,
+                deterministic: true,
+                ..default()
+            };
+            let mut map = world(&Materials::default());
+            step(&mut map, 30, &settings);
+
+            let (mut loaded, loaded_settings) =
+                ChunkMap::<Encoding16>::load(&saved(&map, &settings)[..]).unwrap();
+            assert_eq!(loaded.world_hash(), map.world_hash());
+            assert_eq!(loaded_settings.scheduler, scheduler);
+
+            step(&mut map, 60, &settings);
+            step(&mut loaded, 60, &loaded_settings);
+            assert_eq!(loaded.world_hash(), map.world_hash());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let settings = SimulationSettings::default();
+        let mut bytes = saved(&world(&Materials::default()), &settings);
+        bytes[0] ^= 0xff;
+        assert_invalid(ChunkMap::<Encoding16>::load(&bytes[..]));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let settings = SimulationSettings::default();
+        let mut bytes = saved(&world(&Materials::default()), &settings);
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        bytes[4..6].copy_from_slice(&(version + 1).to_le_bytes());
+        assert_invalid(ChunkMap::<Encoding16>::load(&bytes[..]));
+    }
+
+    #[test]
+    fn rejects_other_encodings() {
+        let settings = SimulationSettings::default();
+        let bytes = saved(&world(&Materials::default()), &settings);
+        assert_invalid(ChunkMap::<Encoding32>::load(&bytes[..]));
+    }
+
+    #[test]
+    fn loads_unregistered_materials() {
+        let settings = SimulationSettings::default();
+        let mut map = world(&Materials::default());
+        // a material another app registered, that `Materials::default` doesn't have
+        let cell = DynamicCell {
+            mass: 2,
+            velocity: I8Vec2::ZERO,
+            offset: I8Vec2::ZERO,
+            material: MaterialId(12),
+        };
+        map.set_packed(ivec2(100, 150), cell.pack());
+
+        let (mut loaded, settings) =
+            ChunkMap::<Encoding16>::load(&saved(&map, &settings)[..]).unwrap();
+        step(&mut loaded, 30, &settings);
+    }
+
+    #[test]
+    fn periodic_world_rejects_chunks_outside_of_it() {
+        let mut map = world(&Materials::default());
+        let rect = IRect::new(0, 0, 2, 2);
+        assert_eq!(
+            map.set_boundary(Boundary::Periodic(IRect::new(0, 0, 1, 2))),
+            Err(BoundaryError::TooSmall)
+        );
+        map.insert(ivec2(3, 1), Chunk::EMPTY).unwrap();
+        assert_eq!(
+            map.set_boundary(Boundary::Periodic(rect)),
+            Err(BoundaryError::OutsideWorld(ivec2(3, 1)))
+        );
+
+        map.remove(ivec2(3, 1));
+        map.set_boundary(Boundary::Periodic(rect)).unwrap();
+        assert_eq!(
+            map.insert(ivec2(-1, 0), Chunk::EMPTY),
+            Err(BoundaryError::OutsideWorld(ivec2(-1, 0)))
+        );
+        assert!(!map.contains(ivec2(-1, 0)));
+    }
+
+    #[test]
+    fn streams_only_its_own_chunks() {
+        let dir = std::env::temp_dir().join(format!("cellular_physics_{}", std::process::id()));
+        let mut store = RegionStore::new(&dir);
+        let materials = Materials::default();
+
+        let mut a = ChunkMap::<Encoding16>::with_seed(1);
+        a.insert(ivec2(5, 0), Chunk::EMPTY).unwrap();
+        a.set_dynamic(ivec2(5 * LEN, 0), MaterialId::SAND, &materials);
+        a.stream(&[IVec2::ZERO], 1, &mut store).unwrap();
+        assert!(!a.contains(ivec2(5, 0)));
+
+        let mut b = ChunkMap::<Encoding16>::with_seed(2);
+        b.stream(&[ivec2(5, 0)], 1, &mut store).unwrap();
+        assert!(!b.contains(ivec2(5, 0)));
+
+        a.stream(&[ivec2(5, 0)], 1, &mut store).unwrap();
+        assert!(a.get(ivec2(5 * LEN, 0)).is_some());
+
+        store.clear().unwrap();
+        assert!(store.load::<Encoding16>(ivec2(5, 0)).unwrap().is_none());
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn saves_streamed_out_chunks() {
+        let dir =
+            std::env::temp_dir().join(format!("cellular_physics_save_{}", std::process::id()));
+        let mut store = RegionStore::new(&dir);
+        let settings = SimulationSettings::default();
+
+        let mut map = world(&Materials::default());
+        let cells = map.iter_some().count();
+        // the sand and water reach into (2, 0)
+        map.stream(&[IVec2::ZERO], 1, &mut store).unwrap();
+        assert!(!map.contains(ivec2(2, 0)));
+        let mut bytes = Vec::new();
+        assert!(map.save(&settings, None, &mut bytes).is_err());
+        bytes.clear();
+        map.save(&settings, Some(&mut store), &mut bytes).unwrap();
+
+        // the store is gone, as it is after a restart
+        store.clear().unwrap();
+        std::fs::remove_dir(dir).unwrap();
+        let (loaded, _) = ChunkMap::<Encoding16>::load(&bytes[..]).unwrap();
+        assert!(loaded.contains(ivec2(2, 0)));
+        assert_eq!(loaded.iter_some().count(), cells);
+    }
+
+    #[test]
+    fn runs_encoding8_with_a_single_material() {
+        ComputeTaskPool::get_or_init(TaskPool::new);
+        let mut materials = Materials::new();
+        let sand = materials.register(Material {
+            name: "sand",
+            fixed: false,
+            mass: 1..=3,
+            elasticity: 4,
+            gravity: 1,
+            color: Color::WHITE,
+        });
+        materials.assert_fits::<Encoding8>();
+
+        let mut map = ChunkMap::<Encoding8>::with_seed(0);
+        map.insert(IVec2::ZERO, Chunk::EMPTY).unwrap();
+        map.set_dynamic(ivec2(10, 40), sand, &materials);
+        for _ in 0..200 {
+            map.sub_step(&SimulationSettings::default(), &materials);
+        }
+        assert_eq!(
+            map.iter_some().map(|(pos, _)| pos.y).collect::<Vec<_>>(),
+            [0]
+        );
+    }
+
+    #[test]
+    fn neighbors_stay_linked() {
+        let materials = Materials::default();
+        let settings = SimulationSettings::default();
+        let mut map = world(&materials);
+        for y in 0..60 {
+            for x in 0..60 {
+                if !map.contains(ivec2(x, y)) {
+                    map.insert(ivec2(x, y), Chunk::EMPTY).unwrap();
+                }
+            }
+        }
+        // holes of every shape, from single chunks to runs along both axes
+        let pattern = |k: IVec2| k.x % 7 == 3 || (k.y % 5 == 1 && k.x % 3 != 0) || k.x == k.y;
+        let ks = map.sorted_keys();
+        for &k in ks.iter().filter(|k| pattern(**k)) {
+            map.remove(k);
+        }
+        for &k in ks.iter().filter(|k| pattern(**k) && (k.x + k.y) % 2 == 0) {
+            map.insert(k, Chunk::EMPTY).unwrap();
+        }
+
+        for (k, chunk) in map.chunks() {
+            for (dir, offset) in OFFSETS {
+                let n = map.boundary.neighbor(k, dir);
+                assert_eq!(chunk.has_neighbor(dir), map.contains(n), "{k} to {offset}");
+                if let Some(neighbor) = map.map.get(&n) {
+                    assert!(neighbor.has_neighbor(dir.inverse()), "{n} to {}", -offset);
+                }
+            }
+        }
+
+        step(&mut map, 5, &settings);
+    }
 }
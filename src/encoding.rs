@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicU8, AtomicU16, AtomicU32, Ordering};
+
+use crate::cell::{FRACTION_BITS, ONE};
+
+/// Bit layout of a `PackedCell`.
+///
+/// Every layout starts with the x and y velocity as `VELOCITY_BITS` wide two's complement integers.
+/// The most negative velocity is never valid so it is used to tag the cell kind:
+/// - invalid x and y: `None`
+/// - invalid x: static, a restitution follows the low bit of y which is always set
+/// - otherwise: dynamic, a mass and the x and y offset follow y
+///
+/// Both kinds then store their `MaterialId` in the `MATERIAL_BITS` above `MATERIAL_SHIFT`.
+///
+/// Layouts with `OFFSET_BITS == 0` don't store the offset within a cell,
+/// it's recovered from the sub step counter instead, so every cell with the same velocity moves in lockstep.
+/// `Encoding32`, the default, keeps a real offset so each cell moves from where it started.
+pub trait CellEncoding: Copy + Eq + Send + Sync + 'static {
+    type Bits: Copy + Eq + Send + Sync;
+    type Atomic: Send + Sync;
+
+    const NONE: Self::Bits;
+
+    const VELOCITY_BITS: u32;
+    const MASS_BITS: u32;
+    /// Either `0` or `FRACTION_BITS`
+    const OFFSET_BITS: u32;
+    const MATERIAL_BITS: u32;
+
+    /// Fastest velocity that fits, never more than a cell per sub step
+    const MAX_SPEED: i8 = min((1 << (Self::VELOCITY_BITS - 1)) - 1, ONE);
+    const MAX_MASS: i8 = 1 << Self::MASS_BITS;
+    /// Number of `MaterialId`s that fit, see `Materials::assert_fits`
+    const MATERIALS: usize = 1 << Self::MATERIAL_BITS;
+
+    const X_SHIFT: u32 = 0;
+    const Y_SHIFT: u32 = Self::VELOCITY_BITS;
+    const MASS_SHIFT: u32 = 2 * Self::VELOCITY_BITS;
+    const OFFSET_X_SHIFT: u32 = Self::MASS_SHIFT + Self::MASS_BITS;
+    const OFFSET_Y_SHIFT: u32 = Self::OFFSET_X_SHIFT + Self::OFFSET_BITS;
+    const RESTITUTION_SHIFT: u32 = Self::VELOCITY_BITS + 1;
+    const MATERIAL_SHIFT: u32 = max(
+        Self::OFFSET_Y_SHIFT + Self::OFFSET_BITS,
+        Self::RESTITUTION_SHIFT + RESTITUTION_BITS,
+    );
+
+    fn to_u32(bits: Self::Bits) -> u32;
+    fn from_u32(bits: u32) -> Self::Bits;
+
+    fn fetch_update(
+        atomic: &Self::Atomic,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: impl FnMut(Self::Bits) -> Option<Self::Bits>,
+    ) -> Result<Self::Bits, Self::Bits>;
+}
+
+pub const RESTITUTION_BITS: u32 = 4;
+
+const fn max(a: u32, b: u32) -> u32 {
+    if a > b { a } else { b }
+}
+
+const fn min(a: i8, b: i8) -> i8 {
+    if a < b { a } else { b }
+}
+
+macro_rules! encoding {
+    (
+        $(#[$meta:meta])*
+        $name:ident, $bits:ty, $atomic:ty,
+        velocity: $v:expr, mass: $m:expr, offset: $o:expr, material: $t:expr
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        pub struct $name;
+
+        impl CellEncoding for $name {
+            type Bits = $bits;
+            type Atomic = $atomic;
+
+            const NONE: $bits = ((1 << ($v - 1)) | (1 << (2 * $v - 1))) as $bits;
+
+            const VELOCITY_BITS: u32 = $v;
+            const MASS_BITS: u32 = $m;
+            const OFFSET_BITS: u32 = $o;
+            const MATERIAL_BITS: u32 = $t;
+
+            fn to_u32(bits: $bits) -> u32 {
+                bits as u32
+            }
+
+            fn from_u32(bits: u32) -> $bits {
+                bits as $bits
+            }
+
+            fn fetch_update(
+                atomic: &$atomic,
+                set_order: Ordering,
+                fetch_order: Ordering,
+                f: impl FnMut($bits) -> Option<$bits>,
+            ) -> Result<$bits, $bits> {
+                atomic.fetch_update(set_order, fetch_order, f)
+            }
+        }
+
+        const _: () = assert!(
+            <$name as CellEncoding>::MATERIAL_SHIFT + $t <= <$bits>::BITS,
+            "layout does not fit"
+        );
+        const _: () = assert!($o == 0 || $o == FRACTION_BITS, "offset must be a whole fraction");
+    };
+}
+
+encoding!(
+    /// Velocity up to `3 / ONE`, 4 masses, no offset and no materials.
+    /// Every cell is `MaterialId(0)`, so it runs with `Materials::new` and a single material registered.
+    Encoding8, u8, AtomicU8,
+    velocity: 3, mass: 2, offset: 0, material: 0
+);
+encoding!(
+    /// Velocity up to a cell per sub step, 8 masses, no offset and 32 materials
+    Encoding16, u16, AtomicU16,
+    velocity: 4, mass: 3, offset: 0, material: 5
+);
+encoding!(
+    /// Velocity up to a cell per sub step, 16 masses, an offset and a full byte of `MaterialId`
+    Encoding32, u32, AtomicU32,
+    velocity: 4, mass: 4, offset: FRACTION_BITS, material: 8
+);
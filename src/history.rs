@@ -0,0 +1,178 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::{cell::PackedCell, chunk_map::ChunkMap, encoding::CellEncoding};
+
+/// A cell an edit changed from `before` to `after`
+#[derive(Clone, Copy)]
+struct CellEdit<E: CellEncoding> {
+    pos: IVec2,
+    before: PackedCell<E>,
+    after: PackedCell<E>,
+}
+
+/// Undoable strokes of edits, each a run of edits between `end_stroke`s.
+///
+/// Undoing a stroke puts back the cells it overwrote and redoing it puts back the cells undoing overwrote,
+/// whatever the simulation did to them in between. Cells whose chunk isn't loaded anymore are skipped.
+#[derive(Resource)]
+pub struct EditHistory<E: CellEncoding> {
+    undo: VecDeque<Vec<CellEdit<E>>>,
+    redo: Vec<Vec<CellEdit<E>>>,
+    stroke: Vec<CellEdit<E>>,
+    /// Most strokes kept, the oldest are dropped past it
+    pub capacity: usize,
+}
+
+impl<E: CellEncoding> Default for EditHistory<E> {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            stroke: Vec::new(),
+            capacity: 64,
+        }
+    }
+}
+
+impl<E: CellEncoding> EditHistory<E> {
+    /// Runs `f` to edit the cell at `pos`, adding it to the current stroke
+    pub fn edit(&mut self, map: &mut ChunkMap<E>, pos: IVec2, f: impl FnOnce(&mut ChunkMap<E>)) {
+        let before = map.get_packed(pos).unwrap_or(PackedCell::NONE);
+        f(map);
+        let after = map.get_packed(pos).unwrap_or(PackedCell::NONE);
+        if before != after {
+            self.stroke.push(CellEdit { pos, before, after });
+        }
+    }
+
+    /// Makes the edits since the last call one undoable step
+    pub fn end_stroke(&mut self) {
+        if self.stroke.is_empty() {
+            return;
+        }
+        self.undo.push_back(std::mem::take(&mut self.stroke));
+        while self.undo.len() > self.capacity {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// `false` if there was nothing to undo
+    pub fn undo(&mut self, map: &mut ChunkMap<E>) -> bool {
+        self.end_stroke();
+        let Some(mut stroke) = self.undo.pop_back() else {
+            return false;
+        };
+        // a cell edited twice gets its first `before` back
+        for edit in stroke.iter_mut().rev() {
+            edit.after = swap(map, edit.pos, edit.before);
+        }
+        self.redo.push(stroke);
+        true
+    }
+
+    /// `false` if there was nothing to redo
+    pub fn redo(&mut self, map: &mut ChunkMap<E>) -> bool {
+        self.end_stroke();
+        let Some(mut stroke) = self.redo.pop() else {
+            return false;
+        };
+        for edit in &mut stroke {
+            edit.before = swap(map, edit.pos, edit.after);
+        }
+        self.undo.push_back(stroke);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.stroke.clear();
+    }
+}
+
+/// Places `p` at `pos`, returning the cell that was there
+fn swap<E: CellEncoding>(map: &mut ChunkMap<E>, pos: IVec2, p: PackedCell<E>) -> PackedCell<E> {
+    let old = map.get_packed(pos).unwrap_or(PackedCell::NONE);
+    map.set_packed(pos, p);
+    old
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+    use super::*;
+    use crate::{
+        cell::StaticCell,
+        chunk::Chunk,
+        encoding::Encoding32,
+        material::{MaterialId, Materials},
+        settings::SimulationSettings,
+    };
+
+    fn stone(restitution: i8) -> PackedCell<Encoding32> {
+        StaticCell {
+            restitution,
+            material: MaterialId::STONE,
+        }
+        .pack()
+    }
+
+    fn one_chunk() -> ChunkMap<Encoding32> {
+        ComputeTaskPool::get_or_init(TaskPool::new);
+        let mut map = ChunkMap::with_seed(0);
+        map.insert(IVec2::ZERO, Chunk::EMPTY).unwrap();
+        map
+    }
+
+    #[test]
+    fn undoes_and_redoes_a_cell_edited_twice() {
+        let materials = Materials::default();
+        let mut map = one_chunk();
+        let mut history = EditHistory::default();
+        let pos = ivec2(20, 20);
+
+        history.edit(&mut map, pos, |map| map.set_packed(pos, stone(2)));
+        history.edit(&mut map, pos, |map| map.set_packed(pos, stone(5)));
+        // sand from the same stroke falls away before it is undone
+        history.edit(&mut map, ivec2(30, 40), |map| {
+            map.set_dynamic(ivec2(30, 40), MaterialId::SAND, &materials)
+        });
+        history.end_stroke();
+        for _ in 0..20 {
+            map.sub_step(&SimulationSettings::default(), &materials);
+        }
+
+        assert!(history.undo(&mut map));
+        assert!(map.get_packed(pos) == Some(PackedCell::NONE));
+        assert!(!history.undo(&mut map));
+
+        assert!(history.redo(&mut map));
+        assert!(map.get_packed(pos) == Some(stone(5)));
+        assert!(!history.redo(&mut map));
+
+        assert!(history.undo(&mut map));
+        assert!(map.get_packed(pos) == Some(PackedCell::NONE));
+    }
+
+    #[test]
+    fn keeps_at_most_capacity_strokes() {
+        let mut map = one_chunk();
+        let mut history = EditHistory {
+            capacity: 2,
+            ..default()
+        };
+        for restitution in 0..3 {
+            let pos = ivec2(restitution as i32, 0);
+            history.edit(&mut map, pos, |map| map.set_packed(pos, stone(restitution)));
+            history.end_stroke();
+        }
+
+        assert!(history.undo(&mut map));
+        assert!(history.undo(&mut map));
+        assert!(!history.undo(&mut map));
+        assert!(map.get_packed(IVec2::ZERO) == Some(stone(0)));
+    }
+}
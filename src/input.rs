@@ -0,0 +1,556 @@
+use bevy::{
+    diagnostic::DiagnosticsStore,
+    input::mouse::{AccumulatedMouseScroll, MouseScrollUnit},
+    prelude::*,
+    window::PrimaryWindow,
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+};
+
+use crate::{
+    brush::{self, BrushShape},
+    chunk_map::ChunkMap,
+    encoding::CellEncoding,
+    history::EditHistory,
+    material::{MaterialId, Materials},
+    overlay::DebugOverlay,
+    plugin::{
+        CELL_SIZE, CellularPhysicsSet, SUB_STEP_TIME, SimulationControl, Streaming, cell_to_world,
+        world_to_cell,
+    },
+    render::ColorMode,
+    settings::{Scheduler, SimulationSettings},
+    world_image::{Palette, export_png, import_png},
+};
+
+pub(crate) fn build<E: CellEncoding>(app: &mut App) {
+    app.init_resource::<CursorCellPos>()
+        .init_resource::<SelectedMaterial>()
+        .init_resource::<Brush>()
+        .init_resource::<EditHistory<E>>()
+        .add_systems(
+            Update,
+            (
+                input_select_material,
+                input_select_brush,
+                (
+                    input_pan_zoom,
+                    update_cursors_cell_pos,
+                    input_edit_cells::<E>,
+                    input_undo_redo::<E>,
+                )
+                    .chain(),
+                log_world_hash::<E>,
+                input_switch_scheduler,
+                input_cycle_color_mode,
+                input_toggle_overlay,
+                input_control_simulation,
+                input_save_load::<E>,
+                input_import_export::<E>,
+            )
+                .in_set(CellularPhysicsSet::Input),
+        );
+}
+
+/// Cell under the cursor, `None` when it's outside the window
+#[derive(Resource, Default)]
+pub struct CursorCellPos(pub Option<IVec2>);
+
+/// Material placed with the left mouse button
+#[derive(Resource, Default)]
+pub struct SelectedMaterial(pub MaterialId);
+
+/// How the mouse edits cells
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Brush {
+    pub tool: Tool,
+    pub shape: BrushShape,
+    /// In cells, `0` edits a single cell
+    pub radius: i32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            tool: Tool::default(),
+            shape: BrushShape::default(),
+            radius: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Tool {
+    /// Stamps the brush along the cursor's path while held
+    #[default]
+    Freehand,
+    /// Stamps the brush along a line from where the button was pressed to where it's released
+    Line,
+    /// Fills the rectangle between where the button was pressed and where it's released
+    Rect,
+    /// Fills the empty region under the cursor
+    Fill,
+}
+
+const MAX_RADIUS: i32 = 32;
+
+const MATERIAL_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+fn input_select_material(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    materials: Res<Materials>,
+    mut selected: ResMut<SelectedMaterial>,
+) {
+    for (i, key) in MATERIAL_KEYS.into_iter().enumerate() {
+        let id = MaterialId(i as u8);
+        if kb_state.just_pressed(key)
+            && let Some(material) = materials.get(id)
+        {
+            info!("selected {}", material.name);
+            selected.0 = id;
+        }
+    }
+}
+
+/// Held while scrolling to resize the brush instead of zooming,
+/// ctrl and scrolling zooms like it does elsewhere
+const RESIZE_KEYS: [KeyCode; 2] = [KeyCode::AltLeft, KeyCode::AltRight];
+
+/// B, L, R and F pick the `Tool`, O switches the brush shape, [ and ] or alt and scrolling resize it
+fn input_select_brush(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    scroll: Res<AccumulatedMouseScroll>,
+    mut brush: ResMut<Brush>,
+) {
+    for (key, tool) in [
+        (KeyCode::KeyB, Tool::Freehand),
+        (KeyCode::KeyL, Tool::Line),
+        (KeyCode::KeyR, Tool::Rect),
+        (KeyCode::KeyF, Tool::Fill),
+    ] {
+        if kb_state.just_pressed(key) {
+            brush.tool = tool;
+            info!("{tool:?} tool");
+        }
+    }
+
+    if kb_state.just_pressed(KeyCode::KeyO) {
+        brush.shape = match brush.shape {
+            BrushShape::Circle => BrushShape::Square,
+            BrushShape::Square => BrushShape::Circle,
+        };
+        info!("{:?} brush", brush.shape);
+    }
+
+    let mut delta = kb_state.just_pressed(KeyCode::BracketRight) as i32
+        - kb_state.just_pressed(KeyCode::BracketLeft) as i32;
+    // `signum` of no scroll is 1
+    if kb_state.any_pressed(RESIZE_KEYS) && scroll.delta.y != 0.0 {
+        delta += scroll.delta.y.signum() as i32;
+    }
+    if delta != 0 {
+        brush.radius = (brush.radius + delta).clamp(0, MAX_RADIUS);
+        info!("brush radius {}", brush.radius);
+    }
+}
+
+/// Zoom limits as world units per pixel
+const MIN_SCALE: f32 = 1.0 / 16.0;
+const MAX_SCALE: f32 = 64.0;
+/// Pixels per second the arrow keys pan by
+const PAN_SPEED: f32 = 600.0;
+/// Zoom factor per line scrolled
+const ZOOM_STEP: f32 = 1.2;
+/// Lines per second holding Q/E scrolls by
+const ZOOM_KEY_RATE: f32 = 4.0;
+
+/// Space with the left mouse button or the arrow keys and WASD pan,
+/// scrolling without `RESIZE_KEYS` or Q/E zoom around the cursor
+fn input_pan_zoom(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    mb_state: Res<ButtonInput<MouseButton>>,
+    scroll: Res<AccumulatedMouseScroll>,
+    time: Res<Time>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    camera: Single<(&mut Transform, &mut Projection), With<Camera2d>>,
+    mut last_cursor: Local<Option<Vec2>>,
+) {
+    let (mut transform, mut projection) = camera.into_inner();
+    let Projection::Orthographic(ortho) = &mut *projection else {
+        return;
+    };
+    let cursor = window.cursor_position();
+    // window pixels are y down, the world is y up
+    let to_world = |pixels: Vec2| pixels * vec2(1.0, -1.0) * ortho.scale;
+
+    if kb_state.pressed(KeyCode::Space)
+        && mb_state.pressed(MouseButton::Left)
+        && let (Some(cursor), Some(last)) = (cursor, *last_cursor)
+    {
+        transform.translation -= to_world(cursor - last).extend(0.0);
+    }
+    *last_cursor = cursor;
+
+    let mut dir = Vec2::ZERO;
+    for (keys, d) in [
+        ([KeyCode::ArrowLeft, KeyCode::KeyA], Vec2::NEG_X),
+        ([KeyCode::ArrowRight, KeyCode::KeyD], Vec2::X),
+        ([KeyCode::ArrowDown, KeyCode::KeyS], Vec2::NEG_Y),
+        ([KeyCode::ArrowUp, KeyCode::KeyW], Vec2::Y),
+    ] {
+        if kb_state.any_pressed(keys) {
+            dir += d;
+        }
+    }
+    transform.translation +=
+        (dir.normalize_or_zero() * PAN_SPEED * ortho.scale * time.delta_secs()).extend(0.0);
+
+    let mut lines = match scroll.unit {
+        _ if kb_state.any_pressed(RESIZE_KEYS) => 0.0,
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / 32.0,
+    };
+    if kb_state.pressed(KeyCode::KeyQ) {
+        lines -= ZOOM_KEY_RATE * time.delta_secs();
+    }
+    if kb_state.pressed(KeyCode::KeyE) {
+        lines += ZOOM_KEY_RATE * time.delta_secs();
+    }
+    if lines == 0.0 {
+        return;
+    }
+
+    let scale = (ortho.scale * ZOOM_STEP.powf(-lines)).clamp(MIN_SCALE, MAX_SCALE);
+    // keeps the point under the cursor in place
+    let anchor = cursor.map_or(Vec2::ZERO, |c| to_world(c - window.size() / 2.0));
+    transform.translation += (anchor * (1.0 - scale / ortho.scale)).extend(0.0);
+    ortho.scale = scale;
+}
+
+fn update_cursors_cell_pos(
+    mut cursor_cell_pos: ResMut<CursorCellPos>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    cam_query: Single<(&Camera, &GlobalTransform)>,
+) {
+    let (camera, camera_transform) = cam_query.into_inner();
+
+    cursor_cell_pos.0 = window
+        .cursor_position()
+        .and_then(|p| camera.viewport_to_world_2d(camera_transform, p).ok())
+        .map(world_to_cell);
+}
+
+/// What a mouse button edits cells into
+#[derive(Clone, Copy)]
+enum Paint {
+    Material(MaterialId),
+    Erase,
+}
+
+/// A mouse button held down since `start`
+struct Drag {
+    button: MouseButton,
+    paint: Paint,
+    start: IVec2,
+    last: IVec2,
+}
+
+/// The left mouse button paints the `SelectedMaterial`, the right one stone and the middle one erases,
+/// in the way the `Brush` says
+#[allow(clippy::too_many_arguments)]
+fn input_edit_cells<E: CellEncoding>(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    mb_state: Res<ButtonInput<MouseButton>>,
+    cursor: Res<CursorCellPos>,
+    selected: Res<SelectedMaterial>,
+    brush: Res<Brush>,
+    materials: Res<Materials>,
+    mut map: ResMut<ChunkMap<E>>,
+    mut history: ResMut<EditHistory<E>>,
+    mut gizmos: Gizmos,
+    mut drag: Local<Option<Drag>>,
+) {
+    // space drags the camera instead
+    if kb_state.pressed(KeyCode::Space) {
+        if drag.take().is_some() {
+            history.end_stroke();
+        }
+        return;
+    }
+
+    if drag.is_none()
+        && let Some(pos) = cursor.0
+    {
+        *drag = [
+            (MouseButton::Left, Paint::Material(selected.0)),
+            (MouseButton::Right, Paint::Material(MaterialId::STONE)),
+            (MouseButton::Middle, Paint::Erase),
+        ]
+        .into_iter()
+        .find(|(button, _)| mb_state.just_pressed(*button))
+        .map(|(button, paint)| Drag {
+            button,
+            paint,
+            start: pos,
+            last: pos,
+        });
+
+        if let Some(d) = &*drag
+            && brush.tool == Tool::Fill
+        {
+            match brush::flood_fill(&map, pos) {
+                Some(cells) => paint(&mut map, &mut history, cells, d.paint, &materials),
+                None => info!("nothing to fill, or more than {} cells", brush::MAX_FILL),
+            }
+        }
+    }
+
+    let Some(d) = &mut *drag else {
+        return;
+    };
+    // keeps going from where the cursor left the window
+    let pos = cursor.0.unwrap_or(d.last);
+    let released = !mb_state.pressed(d.button);
+
+    match brush.tool {
+        Tool::Freehand => {
+            let cells = brush::stroke(d.last, pos, brush.radius, brush.shape);
+            paint(&mut map, &mut history, cells, d.paint, &materials);
+        }
+        Tool::Line if released => {
+            let cells = brush::stroke(d.start, pos, brush.radius, brush.shape);
+            paint(&mut map, &mut history, cells, d.paint, &materials);
+        }
+        Tool::Line => {
+            gizmos.line_2d(cell_to_world(d.start), cell_to_world(pos), Color::WHITE);
+        }
+        Tool::Rect if released => paint(
+            &mut map,
+            &mut history,
+            brush::rect(d.start, pos),
+            d.paint,
+            &materials,
+        ),
+        Tool::Rect => {
+            let (a, b) = (cell_to_world(d.start), cell_to_world(pos));
+            let size = (a - b).abs() + CELL_SIZE;
+            gizmos.rect_2d((a + b) / 2.0, size, Color::WHITE);
+        }
+        Tool::Fill => {}
+    }
+
+    d.last = pos;
+    if released {
+        *drag = None;
+        history.end_stroke();
+    }
+}
+
+fn paint<E: CellEncoding>(
+    map: &mut ChunkMap<E>,
+    history: &mut EditHistory<E>,
+    cells: impl IntoIterator<Item = IVec2>,
+    paint: Paint,
+    materials: &Materials,
+) {
+    for pos in cells {
+        history.edit(map, pos, |map| match paint {
+            Paint::Material(id) if materials[id].fixed => map.set_static(pos, id, materials),
+            Paint::Material(id) => map.set_dynamic(pos, id, materials),
+            Paint::Erase => map.set_none(pos),
+        });
+    }
+}
+
+/// Ctrl and Z undoes the last stroke, ctrl and Y or ctrl, shift and Z redoes it
+fn input_undo_redo<E: CellEncoding>(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    mut map: ResMut<ChunkMap<E>>,
+    mut history: ResMut<EditHistory<E>>,
+) {
+    if !kb_state.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = kb_state.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let undo = kb_state.just_pressed(KeyCode::KeyZ) && !shift;
+    let redo =
+        kb_state.just_pressed(KeyCode::KeyZ) && shift || kb_state.just_pressed(KeyCode::KeyY);
+
+    if undo && !history.undo(&mut map) {
+        info!("nothing to undo");
+    }
+    if redo && !history.redo(&mut map) {
+        info!("nothing to redo");
+    }
+}
+
+fn log_world_hash<E: CellEncoding>(kb_state: Res<ButtonInput<KeyCode>>, map: Res<ChunkMap<E>>) {
+    if kb_state.just_pressed(KeyCode::KeyH) {
+        info!("world hash {:016x}", map.world_hash());
+    }
+}
+
+/// Toggles between schedulers, logging how long the last one took to compare them
+fn input_switch_scheduler(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<SimulationSettings>,
+    mut diagnostics: ResMut<DiagnosticsStore>,
+) {
+    if !kb_state.just_pressed(KeyCode::KeyC) {
+        return;
+    }
+
+    if let Some(diagnostic) = diagnostics.get_mut(&SUB_STEP_TIME) {
+        if let Some(average) = diagnostic.average() {
+            info!(
+                "{:?} scheduler averaged {average:.3}ms per sub step",
+                settings.scheduler
+            );
+        }
+        diagnostic.clear_history();
+    }
+
+    settings.scheduler = match settings.scheduler {
+        Scheduler::Linked => Scheduler::Checkerboard,
+        Scheduler::Checkerboard => Scheduler::Linked,
+    };
+    info!("switched to the {:?} scheduler", settings.scheduler);
+}
+
+/// V cycles through the `ColorMode`s
+fn input_cycle_color_mode(kb_state: Res<ButtonInput<KeyCode>>, mode: Option<ResMut<ColorMode>>) {
+    if let Some(mut mode) = mode
+        && kb_state.just_pressed(KeyCode::KeyV)
+    {
+        *mode = mode.next();
+        info!("colouring cells by {:?}", *mode);
+    }
+}
+
+/// F3 toggles the `DebugOverlay`
+fn input_toggle_overlay(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    overlay: Option<ResMut<DebugOverlay>>,
+) {
+    if let Some(mut overlay) = overlay
+        && kb_state.just_pressed(KeyCode::F3)
+    {
+        overlay.0 = !overlay.0;
+    }
+}
+
+/// P pauses, period runs a tick and comma a sub step, minus and equals halve and double the speed
+/// and G toggles fast forward
+fn input_control_simulation(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    mut control: ResMut<SimulationControl>,
+) {
+    if kb_state.just_pressed(KeyCode::KeyP) {
+        control.paused = !control.paused;
+        info!("{}", if control.paused { "paused" } else { "resumed" });
+    }
+    if kb_state.just_pressed(KeyCode::Period) {
+        control.step();
+    }
+    if kb_state.just_pressed(KeyCode::Comma) {
+        control.step_sub_step();
+    }
+
+    for (key, factor) in [(KeyCode::Minus, 0.5), (KeyCode::Equal, 2.0)] {
+        if kb_state.just_pressed(key) {
+            let scale = control.time_scale() * factor;
+            control.set_time_scale(scale);
+            info!("{}x speed", control.time_scale());
+        }
+    }
+
+    if kb_state.just_pressed(KeyCode::KeyG) {
+        control.fast_forward = !control.fast_forward;
+        info!(
+            "fast forward {}",
+            if control.fast_forward { "on" } else { "off" }
+        );
+    }
+}
+
+const SAVE_PATH: &str = "world.sav";
+
+/// F5 saves the world, streamed out chunks included, F9 loads it back
+fn input_save_load<E: CellEncoding>(
+    mut commands: Commands,
+    kb_state: Res<ButtonInput<KeyCode>>,
+    map: Res<ChunkMap<E>>,
+    settings: Res<SimulationSettings>,
+    streaming: Option<ResMut<Streaming>>,
+    mut history: ResMut<EditHistory<E>>,
+) {
+    if kb_state.just_pressed(KeyCode::F5) {
+        let store = streaming.map(|s| &mut s.into_inner().store);
+        match File::create(SAVE_PATH).and_then(|f| map.save(&settings, store, BufWriter::new(f))) {
+            Ok(()) => info!("saved to {SAVE_PATH}"),
+            Err(e) => error!("saving failed: {e}"),
+        }
+    }
+
+    if kb_state.just_pressed(KeyCode::F9) {
+        match File::open(SAVE_PATH).and_then(|f| ChunkMap::<E>::load(BufReader::new(f))) {
+            Ok((map, settings)) => {
+                commands.insert_resource(map);
+                commands.insert_resource(settings);
+                // the edits were made to another world
+                history.clear();
+                info!("loaded {SAVE_PATH}");
+            }
+            Err(e) => error!("loading failed: {e}"),
+        }
+    }
+}
+
+const IMPORT_PATH: &str = "level.png";
+const EXPORT_PATH: &str = "world.png";
+
+/// F7 imports `IMPORT_PATH` with its bottom left at the origin, F6 exports the world to `EXPORT_PATH`
+fn input_import_export<E: CellEncoding>(
+    kb_state: Res<ButtonInput<KeyCode>>,
+    materials: Res<Materials>,
+    mut map: ResMut<ChunkMap<E>>,
+) {
+    if kb_state.just_pressed(KeyCode::F7) {
+        let palette = Palette::from_materials(&materials);
+        match File::open(IMPORT_PATH).and_then(|f| {
+            import_png(
+                &mut map,
+                BufReader::new(f),
+                IVec2::ZERO,
+                &palette,
+                &materials,
+            )
+        }) {
+            Ok(()) => info!("imported {IMPORT_PATH}"),
+            Err(e) => error!("importing failed: {e}"),
+        }
+    }
+
+    if kb_state.just_pressed(KeyCode::F6)
+        && let Some(rect) = map.cell_bounds()
+    {
+        match File::create(EXPORT_PATH)
+            .and_then(|f| export_png(&map, rect, &materials, BufWriter::new(f)))
+        {
+            Ok(()) => info!("exported to {EXPORT_PATH}"),
+            Err(e) => error!("exporting failed: {e}"),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use std::ops::{Index, RangeInclusive};
+
+use crate::{cell::MAX_RESTITUTION, encoding::CellEncoding};
+
+/// Index into [`Materials`], stored in every packed cell.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct MaterialId(pub u8);
+
+impl MaterialId {
+    pub const SAND: Self = Self(0);
+    pub const WATER: Self = Self(1);
+    pub const OIL: Self = Self(2);
+    pub const GAS: Self = Self(3);
+    pub const STONE: Self = Self(4);
+}
+
+pub struct Material {
+    pub name: &'static str,
+    /// Placed as a `StaticCell` instead of a `DynamicCell`
+    pub fixed: bool,
+    /// Range `set_dynamic` picks a mass from, clamped to the encoding's `MAX_MASS`
+    pub mass: RangeInclusive<i8>,
+    /// Collision restitution in sevenths within `0..=MAX_RESTITUTION`, `7` is perfectly elastic.
+    /// Higher values bounce no harder on their own but make collisions with other materials more elastic.
+    /// Used as the restitution of `StaticCell`s of this material.
+    pub elasticity: i8,
+    /// Velocity removed every gravity sub step, negative values rise
+    pub gravity: i8,
+    pub color: Color,
+}
+
+#[derive(Resource)]
+pub struct Materials(Vec<Material>);
+
+impl Materials {
+    /// No materials, for encodings like `Encoding8` that hold fewer than `Materials::default`'s.
+    /// Register at least one before simulating, unregistered ids fall back to the first.
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn register(&mut self, material: Material) -> MaterialId {
+        assert!(
+            self.0.len() <= u8::MAX as usize,
+            "material registry is full"
+        );
+        assert!(
+            (0..=MAX_RESTITUTION).contains(&material.elasticity),
+            "elasticity of {} is outside 0..={MAX_RESTITUTION}",
+            material.name
+        );
+        assert!(
+            1 <= *material.mass.start() && material.mass.start() <= material.mass.end(),
+            "mass range of {} is empty or below 1",
+            material.name
+        );
+        self.0.push(material);
+        MaterialId((self.0.len() - 1) as u8)
+    }
+
+    /// Panics if some registered ids don't fit in the cells of `E`.
+    /// `register` can't tell as it doesn't know the encoding.
+    pub fn assert_fits<E: CellEncoding>(&self) {
+        assert!(
+            self.0.len() <= E::MATERIALS,
+            "{} materials are registered but the cell encoding only stores {}",
+            self.0.len(),
+            E::MATERIALS
+        );
+    }
+
+    pub fn get(&self, id: MaterialId) -> Option<&Material> {
+        self.0.get(id.0 as usize)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (MaterialId, &Material)> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (MaterialId(i as u8), m))
+    }
+
+    /// Combined elasticity of a collision between two dynamic materials
+    pub fn elasticity(&self, a: MaterialId, b: MaterialId) -> i8 {
+        (self[a].elasticity + self[b].elasticity) / 2
+    }
+}
+
+/// Ids that aren't registered, say from a save made with more materials, get the first material
+impl Index<MaterialId> for Materials {
+    type Output = Material;
+
+    fn index(&self, id: MaterialId) -> &Material {
+        self.get(id).unwrap_or(&self.0[0])
+    }
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        let mut materials = Self::new();
+        let sand = materials.register(Material {
+            name: "sand",
+            fixed: false,
+            mass: 3..=4,
+            elasticity: 4,
+            gravity: 1,
+            color: Color::srgb(0.86, 0.75, 0.45),
+        });
+        let water = materials.register(Material {
+            name: "water",
+            fixed: false,
+            mass: 2..=3,
+            elasticity: 10,
+            gravity: 1,
+            color: Color::srgb(0.2, 0.45, 0.9),
+        });
+        let oil = materials.register(Material {
+            name: "oil",
+            fixed: false,
+            mass: 1..=2,
+            elasticity: 8,
+            gravity: 1,
+            color: Color::srgb(0.35, 0.25, 0.15),
+        });
+        let gas = materials.register(Material {
+            name: "gas",
+            fixed: false,
+            mass: 1..=1,
+            elasticity: 12,
+            gravity: -1,
+            color: Color::srgba(0.8, 0.85, 0.8, 0.5),
+        });
+        let stone = materials.register(Material {
+            name: "stone",
+            fixed: true,
+            mass: 4..=4,
+            elasticity: 15,
+            gravity: 0,
+            color: Color::srgb(0.5, 0.5, 0.5),
+        });
+        debug_assert_eq!(
+            [sand, water, oil, gas, stone],
+            [
+                MaterialId::SAND,
+                MaterialId::WATER,
+                MaterialId::OIL,
+                MaterialId::GAS,
+                MaterialId::STONE
+            ]
+        );
+        materials
+    }
+}
@@ -0,0 +1,77 @@
+use bevy::{math::I8Vec2, prelude::*};
+
+use crate::{
+    OFFSETS,
+    cell::{Cell, ONE},
+    chunk::LEN,
+    chunk_map::ChunkMap,
+    encoding::CellEncoding,
+    plugin::{CELL_SIZE, CellularPhysicsSet, cell_to_world, chunk_center},
+};
+
+pub(crate) fn build<E: CellEncoding>(app: &mut App) {
+    app.init_resource::<DebugOverlay>()
+        .init_gizmo_group::<EdgeGizmos>()
+        .add_systems(
+            Update,
+            draw_overlay::<E>
+                .run_if(|overlay: Res<DebugOverlay>| overlay.0)
+                .in_set(CellularPhysicsSet::Render),
+        );
+}
+
+/// Draws chunk borders, missing neighbor links, edge cells and velocities on top of the cells
+#[derive(Resource, Default)]
+pub struct DebugOverlay(pub bool);
+
+/// Lines as wide as a cell, to shade the edge cells
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct EdgeGizmos;
+
+const BORDER_COLOR: Srgba = Srgba::rgb(0.2, 0.9, 0.3);
+const MISSING_COLOR: Srgba = Srgba::rgb(1.0, 0.2, 0.2);
+const EDGE_COLOR: Srgba = Srgba::new(1.0, 0.9, 0.2, 0.15);
+const VELOCITY_COLOR: Srgba = Srgba::rgb(0.3, 0.7, 1.0);
+
+fn draw_overlay<E: CellEncoding>(
+    mut gizmos: Gizmos,
+    mut edge_gizmos: Gizmos<EdgeGizmos>,
+    mut config_store: ResMut<GizmoConfigStore>,
+    projection: Single<&Projection, With<Camera2d>>,
+    map: Res<ChunkMap<E>>,
+) {
+    // gizmo line widths are in pixels
+    let scale = match *projection {
+        Projection::Orthographic(o) => o.scale,
+        _ => 1.0,
+    };
+    config_store.config_mut::<EdgeGizmos>().0.line.width = CELL_SIZE / scale;
+
+    let size = Vec2::splat(LEN as f32 * CELL_SIZE);
+    for (k, chunk) in map.chunks() {
+        let center = chunk_center(k);
+        gizmos.rect_2d(center, size, BORDER_COLOR);
+        // the rows and columns `is_edge_or_ob`, whose cells are written atomically
+        edge_gizmos.rect_2d(center, size - CELL_SIZE, EDGE_COLOR);
+
+        for (dir, offset) in OFFSETS {
+            if !chunk.has_neighbor(dir) {
+                let pos = center + offset.as_vec2() * (size / 2.0 - 4.0 * CELL_SIZE);
+                gizmos.cross_2d(pos, 2.0 * CELL_SIZE, MISSING_COLOR);
+            }
+        }
+    }
+
+    for (pos, cell) in map.iter_some() {
+        if let Cell::Dynamic(cell) = cell
+            && cell.velocity != I8Vec2::ZERO
+        {
+            // twice as long as the cell moves in a sub step
+            let start = cell_to_world(pos);
+            let delta = cell.velocity.as_vec2() / ONE as f32 * 2.0 * CELL_SIZE;
+            gizmos
+                .arrow_2d(start, start + delta, VELOCITY_COLOR)
+                .with_tip_length(CELL_SIZE / 2.0);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    ecs::schedule::{InternedScheduleLabel, ScheduleLabel},
+    prelude::*,
+};
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    chunk::{Chunk, LEN},
+    chunk_map::ChunkMap,
+    encoding::{CellEncoding, Encoding32},
+    material::Materials,
+    region::RegionStore,
+    settings::SimulationSettings,
+};
+
+/// Wall time of one `ChunkMap::sub_step`
+pub const SUB_STEP_TIME: DiagnosticPath = DiagnosticPath::const_new("sub_step_time");
+
+/// World units per cell
+pub const CELL_SIZE: f32 = 16.0;
+
+/// Position of the center of `cell_pos` in world units, chunk `(0, 0)` is centered on the origin
+pub fn cell_to_world(cell_pos: IVec2) -> Vec2 {
+    (cell_pos.as_vec2() + 0.5 - LEN as f32 / 2.0) * CELL_SIZE
+}
+
+/// The cell `world_pos` falls in
+pub fn world_to_cell(world_pos: Vec2) -> IVec2 {
+    (world_pos / CELL_SIZE + LEN as f32 / 2.0)
+        .floor()
+        .as_ivec2()
+}
+
+/// Position of the center of chunk `k` in world units
+pub fn chunk_center(k: IVec2) -> Vec2 {
+    (k * LEN).as_vec2() * CELL_SIZE
+}
+
+/// What the plugin's systems run in, to order your own around them
+#[derive(SystemSet, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CellularPhysicsSet {
+    /// Editing and debug keys, in `Update`
+    Input,
+    /// Loading and unloading chunks around `StreamFocus` entities, before `Simulate`
+    Stream,
+    /// The sub steps of a tick
+    Simulate,
+    /// Drawing the cells, after `Simulate`
+    Render,
+}
+
+#[derive(Clone)]
+pub struct CellularPhysicsConfig {
+    /// Inclusive chunk positions filled with empty chunks at startup,
+    /// unless the app inserted its own `ChunkMap` before adding the plugin
+    pub world: IRect,
+    /// Ticks per second set on `Time<Fixed>`, `None` leaves it to the app
+    pub tick_rate: Option<f64>,
+    /// Draw the cells, needs the `render` feature
+    pub render: bool,
+    /// Mouse and keyboard editing and debug keys, needs the `render` feature
+    pub input: bool,
+    /// Keep chunks loaded around `StreamFocus` entities and save the rest to disk
+    pub streaming: Option<Streaming>,
+    /// Schedule ticks run in
+    pub schedule: InternedScheduleLabel,
+}
+
+impl Default for CellularPhysicsConfig {
+    fn default() -> Self {
+        Self {
+            world: IRect::new(0, 0, 1, 1),
+            tick_rate: Some(45.0),
+            render: true,
+            input: true,
+            streaming: None,
+            schedule: FixedUpdate.intern(),
+        }
+    }
+}
+
+/// Simulates a `ChunkMap<E>` along with its `Materials` and `SimulationSettings`,
+/// which are only initialized if the app hasn't inserted its own before adding the plugin.
+/// `Materials::default` doesn't fit `Encoding8`, insert ones built with `Materials::new` for it.
+pub struct CellularPhysicsPlugin<E: CellEncoding = Encoding32> {
+    pub config: CellularPhysicsConfig,
+    encoding: PhantomData<E>,
+}
+
+impl<E: CellEncoding> CellularPhysicsPlugin<E> {
+    pub fn new(config: CellularPhysicsConfig) -> Self {
+        Self {
+            config,
+            encoding: PhantomData,
+        }
+    }
+}
+
+impl<E: CellEncoding> Default for CellularPhysicsPlugin<E> {
+    fn default() -> Self {
+        Self::new(CellularPhysicsConfig::default())
+    }
+}
+
+impl<E: CellEncoding> Plugin for CellularPhysicsPlugin<E> {
+    fn build(&self, app: &mut App) {
+        let config = &self.config;
+
+        let new_world = !app.world().contains_resource::<ChunkMap<E>>();
+        if new_world {
+            let mut map = ChunkMap::<E>::default();
+            for y in config.world.min.y..=config.world.max.y {
+                for x in config.world.min.x..=config.world.max.x {
+                    map.insert(ivec2(x, y), Chunk::EMPTY)
+                        .expect("a new map's world isn't periodic");
+                }
+            }
+            app.insert_resource(map);
+        }
+
+        if let Some(hz) = config.tick_rate {
+            app.insert_resource(Time::<Fixed>::from_hz(hz));
+        }
+        app.register_diagnostic(Diagnostic::new(SUB_STEP_TIME).with_suffix("ms"))
+            .init_resource::<Materials>()
+            .init_resource::<SimulationSettings>()
+            .init_resource::<SimulationControl>()
+            .configure_sets(
+                config.schedule,
+                (
+                    CellularPhysicsSet::Stream,
+                    CellularPhysicsSet::Simulate,
+                    CellularPhysicsSet::Render,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                First,
+                (
+                    start_frame,
+                    check_materials::<E>.run_if(resource_changed::<Materials>),
+                ),
+            )
+            .add_systems(
+                config.schedule,
+                step_simulation::<E>.in_set(CellularPhysicsSet::Simulate),
+            );
+
+        if let Some(streaming) = &config.streaming {
+            let mut streaming = streaming.clone();
+            if new_world && let Err(e) = streaming.store.clear() {
+                error!("couldn't clear the last world's regions: {e}");
+            }
+            app.insert_resource(streaming).add_systems(
+                config.schedule,
+                stream_chunks::<E>.in_set(CellularPhysicsSet::Stream),
+            );
+        }
+
+        #[cfg(feature = "render")]
+        {
+            if config.render {
+                crate::render::build::<E>(app, config.schedule);
+                crate::overlay::build::<E>(app);
+            }
+            if config.input {
+                crate::input::build::<E>(app);
+            }
+        }
+        #[cfg(not(feature = "render"))]
+        if config.render || config.input {
+            warn!("cellular physics rendering and input need the `render` feature");
+        }
+    }
+}
+
+/// Chunks further than `radius` chunks from every `StreamFocus` are saved to `store` and unloaded.
+/// `store` is cleared when the plugin creates a new world, a `ChunkMap` inserted beforehand keeps it.
+#[derive(Resource, Clone)]
+pub struct Streaming {
+    pub radius: i32,
+    pub store: RegionStore,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Self {
+            radius: 4,
+            store: RegionStore::new("regions"),
+        }
+    }
+}
+
+/// Keeps the chunks around it loaded
+#[derive(Component)]
+pub struct StreamFocus;
+
+fn stream_chunks<E: CellEncoding>(
+    mut map: ResMut<ChunkMap<E>>,
+    mut streaming: ResMut<Streaming>,
+    focus: Query<&GlobalTransform, With<StreamFocus>>,
+) {
+    let focus = focus
+        .iter()
+        .map(|t| world_to_cell(t.translation().truncate()).div_euclid(IVec2::splat(LEN)))
+        .collect::<Vec<_>>();
+    // without a focus everything would be unloaded
+    if focus.is_empty() {
+        return;
+    }
+
+    let Streaming { radius, store } = &mut *streaming;
+    if let Err(e) = map.stream(&focus, *radius, store) {
+        error!("chunk streaming failed: {e}");
+    }
+}
+
+pub const MIN_TIME_SCALE: f32 = 0.25;
+pub const MAX_TIME_SCALE: f32 = 8.0;
+
+/// Pausing, stepping and the speed of the simulation.
+/// Everything runs whole `ChunkMap::sub_step`s so gravity keeps its cadence.
+#[derive(Resource)]
+pub struct SimulationControl {
+    pub paused: bool,
+    /// Runs as many ticks as fit in `frame_budget` instead of following `time_scale`
+    pub fast_forward: bool,
+    /// Time fast forwarding may take each frame, shared by every tick in the frame
+    pub frame_budget: Duration,
+    time_scale: f32,
+    /// Time ticks took so far this frame
+    frame_time: Duration,
+    /// Fraction of a tick carried over to the next one by slow time scales
+    tick_fraction: f32,
+    queued_ticks: u32,
+    queued_sub_steps: u32,
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            fast_forward: false,
+            frame_budget: Duration::from_millis(12),
+            time_scale: 1.0,
+            frame_time: Duration::ZERO,
+            tick_fraction: 0.0,
+            queued_ticks: 0,
+            queued_sub_steps: 0,
+        }
+    }
+}
+
+impl SimulationControl {
+    /// Ticks run per tick of the schedule, on average
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Clamped to `MIN_TIME_SCALE..=MAX_TIME_SCALE`
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.time_scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+
+    /// Runs one more tick of `SimulationSettings::sub_steps` next time, even when paused
+    pub fn step(&mut self) {
+        self.queued_ticks += 1;
+    }
+
+    /// Runs one more sub step next time, even when paused
+    pub fn step_sub_step(&mut self) {
+        self.queued_sub_steps += 1;
+    }
+
+    /// Sub steps to run this tick, other than fast forwarded ones
+    fn take_sub_steps(&mut self, sub_steps: u32) -> u32 {
+        let mut ticks = std::mem::take(&mut self.queued_ticks);
+        if !self.paused && !self.fast_forward {
+            self.tick_fraction += self.time_scale;
+            let whole = self.tick_fraction.floor();
+            self.tick_fraction -= whole;
+            ticks += whole as u32;
+        }
+        ticks * sub_steps + std::mem::take(&mut self.queued_sub_steps)
+    }
+}
+
+/// Materials registered after the plugin was built are checked too
+fn check_materials<E: CellEncoding>(materials: Res<Materials>) {
+    materials.assert_fits::<E>();
+}
+
+fn start_frame(mut control: ResMut<SimulationControl>) {
+    control.frame_time = Duration::ZERO;
+}
+
+fn step_simulation<E: CellEncoding>(
+    mut map: ResMut<ChunkMap<E>>,
+    settings: Res<SimulationSettings>,
+    materials: Res<Materials>,
+    mut control: ResMut<SimulationControl>,
+    mut diagnostics: Diagnostics,
+) {
+    let start = Instant::now();
+    let mut sub_steps = control.take_sub_steps(settings.sub_steps);
+    for _ in 0..sub_steps {
+        map.sub_step(&settings, &materials);
+    }
+    if control.fast_forward && !control.paused && settings.sub_steps != 0 {
+        // whole ticks, at least one so it's never slower than not fast forwarding
+        loop {
+            for _ in 0..settings.sub_steps {
+                map.sub_step(&settings, &materials);
+            }
+            sub_steps += settings.sub_steps;
+            if control.frame_time + start.elapsed() >= control.frame_budget {
+                break;
+            }
+        }
+    }
+    control.frame_time += start.elapsed();
+
+    if sub_steps == 0 {
+        return;
+    }
+    diagnostics.add_measurement(&SUB_STEP_TIME, || {
+        start.elapsed().as_secs_f64() * 1000.0 / sub_steps as f64
+    });
+}
@@ -0,0 +1,161 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use crate::{chunk::Chunk, encoding::CellEncoding};
+
+const REGION_BITS: i32 = 4;
+/// Chunks per region file on each axis
+const REGION_LEN: i32 = 1 << REGION_BITS;
+const REGION_AREA: usize = REGION_LEN.pow(2) as usize;
+
+const SLOT_SIZE: u64 = 8;
+const HEADER_SIZE: u64 = REGION_AREA as u64 * SLOT_SIZE;
+
+/// Where in its region file a chunk was last saved, `len == 0` if it never was
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    offset: u32,
+    len: u32,
+}
+
+/// Chunks saved to region files of `REGION_LEN` by `REGION_LEN` chunks.
+///
+/// A region file starts with a little endian `Slot` per chunk, row by row, followed by the chunks' cells.
+/// A chunk saved again overwrites its old cells, unless they were saved with another encoding
+/// and are a different length, then it's appended anew leaving the old cells unused.
+#[derive(Clone)]
+pub struct RegionStore {
+    dir: PathBuf,
+    /// Slots of every region read or written so far
+    slots: HashMap<IVec2, Box<[Slot; REGION_AREA]>>,
+}
+
+impl RegionStore {
+    /// Region files go in `dir`, which is created on the first save
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            slots: HashMap::default(),
+        }
+    }
+
+    /// Deletes every region file in `dir`, for a new world that shouldn't load another one's chunks
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.slots.clear();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let is_region = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+                n.strip_prefix("r.")
+                    .and_then(|n| n.strip_suffix(".bin"))
+                    .is_some_and(|n| n.split('.').all(|x| x.parse::<i32>().is_ok()))
+            });
+            if is_region {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save<E: CellEncoding>(&mut self, k: IVec2, chunk: &Chunk<E>) -> io::Result<()> {
+        let (region, i) = split(k);
+        let bytes = chunk.cell_bytes();
+
+        let old = self.load_slots(region)?[i];
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path(region))?;
+
+        // chunks of an encoding are all the same length, so one saved before can be overwritten
+        if old.len as usize == bytes.len() {
+            file.seek(SeekFrom::Start(old.offset as u64))?;
+            file.write_all(&bytes)?;
+            return Ok(());
+        }
+
+        let end = file.seek(SeekFrom::End(0))?;
+        if end < HEADER_SIZE {
+            file.set_len(HEADER_SIZE)?;
+        }
+        let offset = end.max(HEADER_SIZE);
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&bytes)?;
+
+        let slot = Slot {
+            offset: u32::try_from(offset)
+                .map_err(|_| io::Error::new(ErrorKind::FileTooLarge, "region file is full"))?,
+            len: bytes.len() as u32,
+        };
+        file.seek(SeekFrom::Start(i as u64 * SLOT_SIZE))?;
+        file.write_all(&slot.offset.to_le_bytes())?;
+        file.write_all(&slot.len.to_le_bytes())?;
+
+        self.slots.get_mut(&region).unwrap()[i] = slot;
+        Ok(())
+    }
+
+    /// `None` if the chunk at `k` was never saved
+    pub fn load<E: CellEncoding>(&mut self, k: IVec2) -> io::Result<Option<Chunk<E>>> {
+        let (region, i) = split(k);
+        let slot = self.load_slots(region)?[i];
+        if slot.len == 0 {
+            return Ok(None);
+        }
+
+        if slot.len as usize != Chunk::<E>::SAVED_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "chunk was saved with a different encoding",
+            ));
+        }
+
+        let mut file = File::open(self.path(region))?;
+        file.seek(SeekFrom::Start(slot.offset as u64))?;
+        let mut bytes = vec![0; slot.len as usize];
+        file.read_exact(&mut bytes)?;
+
+        Ok(Some(Chunk::from_cell_bytes(&bytes)))
+    }
+
+    fn load_slots(&mut self, region: IVec2) -> io::Result<&[Slot; REGION_AREA]> {
+        if !self.slots.contains_key(&region) {
+            let mut slots = Box::new([Slot::default(); REGION_AREA]);
+            match File::open(self.path(region)) {
+                Ok(mut file) => {
+                    let mut header = vec![0; HEADER_SIZE as usize];
+                    file.read_exact(&mut header)?;
+                    for (slot, bytes) in slots.iter_mut().zip(header.chunks_exact(8)) {
+                        slot.offset = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+                        slot.len = u32::from_le_bytes(bytes[4..].try_into().unwrap());
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            self.slots.insert(region, slots);
+        }
+        Ok(&self.slots[&region])
+    }
+
+    fn path(&self, region: IVec2) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.bin", region.x, region.y))
+    }
+}
+
+/// The region `k` is in and its slot there
+fn split(k: IVec2) -> (IVec2, usize) {
+    let local = k & (REGION_LEN - 1);
+    (k >> REGION_BITS, (local.y * REGION_LEN + local.x) as usize)
+}
@@ -0,0 +1,311 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    ecs::schedule::InternedScheduleLabel,
+    image::ImageSampler,
+    math::I8Vec2,
+    platform::collections::HashMap,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::{
+    cell::{Cell, MAX_RESTITUTION},
+    chunk::{self, LEN},
+    chunk_map::ChunkMap,
+    encoding::CellEncoding,
+    material::Materials,
+    plugin::{CELL_SIZE, CellularPhysicsSet, chunk_center},
+    settings::SimulationSettings,
+};
+
+pub(crate) fn build<E: CellEncoding>(app: &mut App, schedule: InternedScheduleLabel) {
+    app.init_resource::<ChunkSprites>()
+        .init_resource::<ColorMode>()
+        .add_systems(Startup, spawn_legend)
+        .add_systems(
+            schedule,
+            draw_chunks::<E>.in_set(CellularPhysicsSet::Render),
+        )
+        .add_systems(
+            Update,
+            update_legend::<E>
+                .run_if(resource_changed::<ColorMode>)
+                .in_set(CellularPhysicsSet::Render),
+        );
+}
+
+/// What the colour of a cell shows
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ColorMode {
+    #[default]
+    Material,
+    /// Static, dynamic and empty cells apart
+    Kind,
+    /// Mass of dynamic cells on a gradient
+    Mass,
+    /// Direction of dynamic cells as hue and speed as brightness
+    Velocity,
+    /// Speed of dynamic cells on a gradient
+    Speed,
+    /// Restitution of static cells on a gradient
+    Restitution,
+}
+
+impl ColorMode {
+    pub const ALL: [Self; 6] = [
+        Self::Material,
+        Self::Kind,
+        Self::Mass,
+        Self::Velocity,
+        Self::Speed,
+        Self::Restitution,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+const EMPTY_COLOR: Srgba = Srgba::rgb(0.05, 0.05, 0.08);
+const STATIC_COLOR: Srgba = Srgba::rgb(0.45, 0.45, 0.45);
+const DYNAMIC_COLOR: Srgba = Srgba::rgb(0.9, 0.6, 0.2);
+/// Cells a mode says nothing about
+const MUTED_COLOR: Srgba = Srgba::rgb(0.2, 0.2, 0.2);
+
+const GRADIENT: [Srgba; 4] = [
+    Srgba::rgb(0.1, 0.1, 0.45),
+    Srgba::rgb(0.1, 0.6, 0.6),
+    Srgba::rgb(0.95, 0.8, 0.2),
+    Srgba::rgb(1.0, 1.0, 1.0),
+];
+
+/// `t` in `0.0..=1.0` along `GRADIENT`
+fn gradient(t: f32) -> Srgba {
+    let t = t.clamp(0.0, 1.0) * (GRADIENT.len() - 1) as f32;
+    let i = (t as usize).min(GRADIENT.len() - 2);
+    GRADIENT[i].mix(&GRADIENT[i + 1], t - i as f32)
+}
+
+fn velocity_color(velocity: I8Vec2, max_speed: i8) -> Srgba {
+    let v = velocity.as_vec2();
+    let hue = v.y.atan2(v.x).to_degrees().rem_euclid(360.0);
+    let t = v.length() / max_speed.max(1) as f32;
+    Hsva::hsv(hue, 1.0, 0.25 + 0.75 * t.min(1.0)).into()
+}
+
+fn cell_color<E: CellEncoding>(
+    cell: Option<Cell>,
+    mode: ColorMode,
+    materials: &Materials,
+    max_speed: i8,
+) -> [u8; 4] {
+    let color = match (mode, cell) {
+        (ColorMode::Kind, None) => EMPTY_COLOR,
+        (_, None) => return [0; 4],
+        (ColorMode::Material, Some(c)) => materials
+            .get(c.material())
+            .map_or(Color::WHITE, |m| m.color)
+            .into(),
+        (ColorMode::Kind, Some(Cell::Static(_))) => STATIC_COLOR,
+        (ColorMode::Kind, Some(Cell::Dynamic(_))) => DYNAMIC_COLOR,
+        (ColorMode::Mass, Some(Cell::Dynamic(c))) => {
+            gradient((c.mass - 1) as f32 / (E::MAX_MASS - 1).max(1) as f32)
+        }
+        (ColorMode::Velocity, Some(Cell::Dynamic(c))) => velocity_color(c.velocity, max_speed),
+        (ColorMode::Speed, Some(Cell::Dynamic(c))) => {
+            gradient(c.velocity.as_vec2().length() / max_speed.max(1) as f32)
+        }
+        (ColorMode::Restitution, Some(Cell::Static(c))) => {
+            gradient(c.restitution as f32 / MAX_RESTITUTION as f32)
+        }
+        (_, Some(_)) => MUTED_COLOR,
+    };
+    color.to_u8_array()
+}
+
+/// Sprite entity and texture of every chunk, one pixel per cell
+#[derive(Resource, Default)]
+struct ChunkSprites(HashMap<IVec2, (Entity, Handle<Image>)>);
+
+/// Redraws the cells that changed, spawning sprites for new chunks and despawning those of removed ones.
+/// Everything is redrawn when the `ColorMode` changes.
+fn draw_chunks<E: CellEncoding>(
+    mut commands: Commands,
+    mut sprites: ResMut<ChunkSprites>,
+    mut images: ResMut<Assets<Image>>,
+    mut map: ResMut<ChunkMap<E>>,
+    materials: Res<Materials>,
+    settings: Res<SimulationSettings>,
+    mode: Res<ColorMode>,
+) {
+    sprites.0.retain(|&k, (entity, _)| {
+        let keep = map.contains(k);
+        if !keep {
+            commands.entity(*entity).despawn();
+        }
+        keep
+    });
+
+    let max_speed = settings.max_speed::<E>();
+    for (k, chunk) in map.chunks_mut() {
+        let mut changed = chunk.take_changed();
+        if mode.is_changed() {
+            changed = IRect::new(0, 0, LEN - 1, LEN - 1);
+        }
+        if chunk::is_empty(changed) {
+            continue;
+        }
+
+        let handle = &sprites
+            .0
+            .entry(k)
+            .or_insert_with(|| spawn_sprite(&mut commands, &mut images, k))
+            .1;
+        let Some(data) = images.get_mut(handle).and_then(|i| i.data.as_mut()) else {
+            continue;
+        };
+        for y in changed.min.y..=changed.max.y {
+            // rows go top to bottom
+            let row = (LEN - 1 - y) as usize * LEN as usize;
+            for x in changed.min.x..=changed.max.x {
+                let cell = chunk.get(ivec2(x, y).as_uvec2());
+                let color = cell_color::<E>(cell, *mode, &materials, max_speed);
+                let i = (row + x as usize) * 4;
+                data[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+fn spawn_sprite(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    k: IVec2,
+) -> (Entity, Handle<Image>) {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: LEN as u32,
+            height: LEN as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    let handle = images.add(image);
+
+    let entity = commands
+        .spawn((
+            Sprite {
+                image: handle.clone(),
+                custom_size: Some(Vec2::splat(LEN as f32 * CELL_SIZE)),
+                ..default()
+            },
+            Transform::from_translation(chunk_center(k).extend(0.0)),
+        ))
+        .id();
+    (entity, handle)
+}
+
+/// Panel in the top left listing what the colours of the current `ColorMode` mean
+#[derive(Component)]
+struct Legend;
+
+fn spawn_legend(mut commands: Commands) {
+    commands.spawn((
+        Legend,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Px(8.0),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(2.0),
+            padding: UiRect::all(Val::Px(6.0)),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.6)),
+    ));
+}
+
+fn update_legend<E: CellEncoding>(
+    mut commands: Commands,
+    legend: Single<Entity, With<Legend>>,
+    mode: Res<ColorMode>,
+    materials: Res<Materials>,
+) {
+    // a few stops of `GRADIENT` labelled with the value at `t * max`
+    let stops =
+        |max: f32, label: fn(f32) -> String| [0.0, 0.5, 1.0].map(|t| (gradient(t), label(t * max)));
+    let mut entries = Vec::new();
+    match *mode {
+        ColorMode::Material => {
+            for (_, m) in materials.iter() {
+                entries.push((m.color.into(), m.name.to_string()));
+            }
+        }
+        ColorMode::Kind => {
+            entries.push((EMPTY_COLOR, "empty".to_string()));
+            entries.push((STATIC_COLOR, "static".to_string()));
+            entries.push((DYNAMIC_COLOR, "dynamic".to_string()));
+        }
+        ColorMode::Mass => {
+            for mass in 1..=E::MAX_MASS {
+                let t = (mass - 1) as f32 / (E::MAX_MASS - 1).max(1) as f32;
+                entries.push((gradient(t), format!("mass {mass}")));
+            }
+        }
+        ColorMode::Velocity => {
+            for (name, v) in [
+                ("right", I8Vec2::X),
+                ("up", I8Vec2::Y),
+                ("left", I8Vec2::NEG_X),
+                ("down", I8Vec2::NEG_Y),
+                ("at rest", I8Vec2::ZERO),
+            ] {
+                entries.push((velocity_color(v, 1), name.to_string()));
+            }
+        }
+        ColorMode::Speed => {
+            entries.extend(stops(1.0, |t| format!("{t:.1} of max speed")));
+        }
+        ColorMode::Restitution => {
+            entries.extend(stops(MAX_RESTITUTION as f32, |r| {
+                format!("restitution {}", r.round())
+            }));
+        }
+    }
+    if !matches!(*mode, ColorMode::Material | ColorMode::Kind) {
+        entries.push((MUTED_COLOR, "n/a".to_string()));
+    }
+
+    commands
+        .entity(*legend)
+        .despawn_children()
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(format!("{:?}", *mode)),
+                TextFont::from_font_size(14.0),
+            ));
+            for (color, label) in entries {
+                parent
+                    .spawn(Node {
+                        column_gap: Val::Px(6.0),
+                        align_items: AlignItems::Center,
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn((
+                            Node {
+                                width: Val::Px(12.0),
+                                height: Val::Px(12.0),
+                                ..default()
+                            },
+                            BackgroundColor(color.into()),
+                        ));
+                        row.spawn((Text::new(label), TextFont::from_font_size(12.0)));
+                    });
+            }
+        });
+}
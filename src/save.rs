@@ -0,0 +1,211 @@
+use bevy::prelude::*;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::{
+    chunk::Boundary,
+    encoding::CellEncoding,
+    settings::{Scheduler, SimulationSettings},
+};
+
+const MAGIC: [u8; 4] = *b"CPHY";
+/// Bumped whenever the layout below changes, older versions are rejected
+const VERSION: u16 = 2;
+
+// A save is `MAGIC` and `VERSION` followed by a zlib stream of little endian fields, in order:
+// - the `CellEncoding`'s cell size and field widths
+// - `SimulationSettings`
+// - the sub step counter and the rng seed and word position
+// - the `Boundary`, open world bounds and absorbed mass
+// - the chunk count, then each chunk's position, activity and read buffer, streamed out ones included
+
+/// Writes the fields of a save, see `ChunkMap::save`
+pub struct SaveWriter<W: Write>(ZlibEncoder<W>);
+
+impl<W: Write> SaveWriter<W> {
+    pub fn new<E: CellEncoding>(mut w: W) -> io::Result<Self> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        let mut w = Self(ZlibEncoder::new(w, Compression::default()));
+        w.encoding::<E>()?;
+        Ok(w)
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.0.finish()
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)
+    }
+
+    pub fn u8(&mut self, x: u8) -> io::Result<()> {
+        self.bytes(&[x])
+    }
+
+    pub fn bool(&mut self, x: bool) -> io::Result<()> {
+        self.u8(x as u8)
+    }
+
+    pub fn u32(&mut self, x: u32) -> io::Result<()> {
+        self.bytes(&x.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, x: u64) -> io::Result<()> {
+        self.bytes(&x.to_le_bytes())
+    }
+
+    pub fn u128(&mut self, x: u128) -> io::Result<()> {
+        self.bytes(&x.to_le_bytes())
+    }
+
+    pub fn ivec2(&mut self, v: IVec2) -> io::Result<()> {
+        self.u32(v.x as u32)?;
+        self.u32(v.y as u32)
+    }
+
+    pub fn irect(&mut self, rect: IRect) -> io::Result<()> {
+        self.ivec2(rect.min)?;
+        self.ivec2(rect.max)
+    }
+
+    fn encoding<E: CellEncoding>(&mut self) -> io::Result<()> {
+        self.bytes(&encoding_tag::<E>())
+    }
+
+    pub fn settings(&mut self, settings: &SimulationSettings) -> io::Result<()> {
+        self.u8(settings.max_speed as u8)?;
+        self.u32(settings.sub_steps)?;
+        self.u32(settings.gravity_interval)?;
+        self.bool(settings.deterministic)?;
+        self.u32(settings.sleep_after)?;
+        self.u8(match settings.scheduler {
+            Scheduler::Linked => 0,
+            Scheduler::Checkerboard => 1,
+        })
+    }
+
+    pub fn boundary(&mut self, boundary: Boundary) -> io::Result<()> {
+        match boundary {
+            Boundary::Reflective(restitution) => {
+                self.u8(0)?;
+                self.u8(restitution as u8)
+            }
+            Boundary::Absorbing => self.u8(1),
+            Boundary::Periodic(rect) => {
+                self.u8(2)?;
+                self.irect(rect)
+            }
+        }
+    }
+}
+
+/// Reads the fields `SaveWriter` wrote, in the same order
+pub struct SaveReader<R: Read>(ZlibDecoder<R>);
+
+impl<R: Read> SaveReader<R> {
+    pub fn new<E: CellEncoding>(mut r: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid("not a save"));
+        }
+        let mut version = [0; 2];
+        r.read_exact(&mut version)?;
+        if u16::from_le_bytes(version) != VERSION {
+            return Err(invalid("unsupported save version"));
+        }
+
+        let mut r = Self(ZlibDecoder::new(r));
+        let mut tag = [0; 5];
+        r.bytes(&mut tag)?;
+        if tag != encoding_tag::<E>() {
+            return Err(invalid("save uses a different encoding"));
+        }
+        Ok(r)
+    }
+
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("invalid bool")),
+        }
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub fn u128(&mut self) -> io::Result<u128> {
+        self.array().map(u128::from_le_bytes)
+    }
+
+    pub fn ivec2(&mut self) -> io::Result<IVec2> {
+        Ok(ivec2(self.u32()? as i32, self.u32()? as i32))
+    }
+
+    pub fn irect(&mut self) -> io::Result<IRect> {
+        Ok(IRect {
+            min: self.ivec2()?,
+            max: self.ivec2()?,
+        })
+    }
+
+    pub fn settings(&mut self) -> io::Result<SimulationSettings> {
+        Ok(SimulationSettings {
+            max_speed: self.u8()? as i8,
+            sub_steps: self.u32()?,
+            gravity_interval: self.u32()?,
+            deterministic: self.bool()?,
+            sleep_after: self.u32()?,
+            scheduler: match self.u8()? {
+                0 => Scheduler::Linked,
+                1 => Scheduler::Checkerboard,
+                _ => return Err(invalid("unknown scheduler")),
+            },
+        })
+    }
+
+    pub fn boundary(&mut self) -> io::Result<Boundary> {
+        match self.u8()? {
+            0 => Ok(Boundary::Reflective(self.u8()? as i8)),
+            1 => Ok(Boundary::Absorbing),
+            2 => Ok(Boundary::Periodic(self.irect()?)),
+            _ => Err(invalid("unknown boundary")),
+        }
+    }
+}
+
+/// Cells from a different layout would load as garbage
+fn encoding_tag<E: CellEncoding>() -> [u8; 5] {
+    [
+        size_of::<E::Bits>() as u8,
+        E::VELOCITY_BITS as u8,
+        E::MASS_BITS as u8,
+        E::OFFSET_BITS as u8,
+        E::MATERIAL_BITS as u8,
+    ]
+}
+
+pub fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
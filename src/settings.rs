@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+
+use crate::{cell::ONE, encoding::CellEncoding};
+
+/// How `ChunkMap::sub_step` runs chunks in parallel
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Scheduler {
+    /// Every active chunk at once, neighbors are reached through raw pointers
+    /// and edge cells they share are written atomically
+    #[default]
+    Linked,
+    /// 9 interleaved sets of chunks in turn so no two running chunks share a neighbor,
+    /// each gets exclusive borrows of its neighborhood and moves cells with plain reads and writes.
+    /// Always deterministic.
+    Checkerboard,
+}
+
+#[derive(Resource, Clone)]
+pub struct SimulationSettings {
+    /// Fastest a cell can move in `1 / ONE` cells per sub step, at most `ONE`.
+    /// Clamped to the `CellEncoding`'s `MAX_SPEED`, which is `ONE` for every layout
+    /// but `Encoding8`, see `SimulationSettings::max_speed`
+    pub max_speed: i8,
+    /// Sub steps run every fixed tick
+    pub sub_steps: u32,
+    /// Gravity is applied every this many sub steps
+    pub gravity_interval: u32,
+    /// Resolve moves into chunk edges in a fixed order so results don't depend on the threads,
+    /// at the cost of doing that part single threaded
+    pub deterministic: bool,
+    /// Sub steps a chunk keeps running after its last move before it sleeps,
+    /// should be a few times longer than a cell at rest takes to start falling
+    pub sleep_after: u32,
+    pub scheduler: Scheduler,
+}
+
+impl SimulationSettings {
+    /// The `max_speed` cells of `E` actually move at
+    pub fn max_speed<E: CellEncoding>(&self) -> i8 {
+        self.max_speed.clamp(0, E::MAX_SPEED)
+    }
+
+    pub fn is_gravity_sub_step(&self, n: u32) -> bool {
+        n.is_multiple_of(self.gravity_interval.max(1))
+    }
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            max_speed: ONE,
+            sub_steps: 1,
+            gravity_interval: 3,
+            deterministic: false,
+            sleep_after: 32,
+            scheduler: Scheduler::default(),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use bevy::{math::I8Vec2, platform::collections::HashMap, prelude::*};
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use std::io::{self, BufRead, Seek, Write};
+
+use crate::{
+    cell::{Cell, DynamicCell, MAX_RESTITUTION, StaticCell},
+    chunk::{Chunk, LEN},
+    chunk_map::ChunkMap,
+    encoding::CellEncoding,
+    material::{MaterialId, Materials},
+    save::invalid,
+};
+
+/// What a colour in an imported image becomes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PaletteEntry {
+    None,
+    Static {
+        material: MaterialId,
+        /// In sevenths, see `StaticCell`
+        restitution: i8,
+    },
+    Dynamic {
+        material: MaterialId,
+        mass: i8,
+        /// In `1 / ONE` cells per sub step
+        velocity: I8Vec2,
+    },
+}
+
+/// Maps exact RGBA colours to cells, pixels of any other colour are skipped
+#[derive(Clone, Default)]
+pub struct Palette(HashMap<[u8; 4], PaletteEntry>);
+
+impl Palette {
+    pub fn with(mut self, color: [u8; 4], entry: PaletteEntry) -> Self {
+        self.0.insert(color, entry);
+        self
+    }
+
+    /// Every material's colour as that material at rest, fixed ones static with their elasticity,
+    /// and transparent black as `None`. The same colours `export_png` writes.
+    pub fn from_materials(materials: &Materials) -> Self {
+        let mut palette = Self::default().with([0; 4], PaletteEntry::None);
+        for (material, m) in materials.iter() {
+            let entry = if m.fixed {
+                PaletteEntry::Static {
+                    material,
+                    restitution: m.elasticity,
+                }
+            } else {
+                PaletteEntry::Dynamic {
+                    material,
+                    mass: *m.mass.end(),
+                    velocity: I8Vec2::ZERO,
+                }
+            };
+            palette = palette.with(m.color.to_srgba().to_u8_array(), entry);
+        }
+        palette
+    }
+
+    /// The first material an entry uses that isn't in `materials` or doesn't fit in `E`
+    fn unregistered<E: CellEncoding>(&self, materials: &Materials) -> Option<MaterialId> {
+        self.0.values().find_map(|entry| match *entry {
+            PaletteEntry::None => None,
+            PaletteEntry::Static { material, .. } | PaletteEntry::Dynamic { material, .. } => {
+                (materials.get(material).is_none() || material.0 as usize >= E::MATERIALS)
+                    .then_some(material)
+            }
+        })
+    }
+
+    fn cell<E: CellEncoding>(&self, color: [u8; 4]) -> Option<Option<Cell>> {
+        Some(match *self.0.get(&color)? {
+            PaletteEntry::None => None,
+            PaletteEntry::Static {
+                material,
+                restitution,
+            } => Some(Cell::Static(StaticCell {
+                restitution: restitution.clamp(0, MAX_RESTITUTION),
+                material,
+            })),
+            PaletteEntry::Dynamic {
+                material,
+                mass,
+                velocity,
+            } => Some(Cell::Dynamic(DynamicCell {
+                mass: mass.clamp(1, E::MAX_MASS),
+                velocity,
+                offset: I8Vec2::ZERO,
+                material,
+            })),
+        })
+    }
+}
+
+/// Places the cells `palette` maps an image's pixels to with its bottom left pixel at `offset`,
+/// inserting the chunks it covers that are missing. Fails without placing anything
+/// if `palette` uses a material that isn't in `materials` or doesn't fit in `E`.
+pub fn import_png<E: CellEncoding>(
+    map: &mut ChunkMap<E>,
+    r: impl BufRead + Seek,
+    offset: IVec2,
+    palette: &Palette,
+    materials: &Materials,
+) -> io::Result<()> {
+    if let Some(id) = palette.unregistered::<E>(materials) {
+        return Err(invalid(&format!(
+            "palette uses unregistered or unrepresentable material {}",
+            id.0
+        )));
+    }
+
+    let mut decoder = Decoder::new(r);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let len = reader
+        .output_buffer_size()
+        .ok_or_else(|| invalid("image too large"))?;
+    let mut buf = vec![0; len];
+    let info = reader.next_frame(&mut buf)?;
+
+    let size = ivec2(info.width as i32, info.height as i32);
+    let chunks = IRect {
+        min: offset.div_euclid(IVec2::splat(LEN)),
+        max: (offset + size - 1).div_euclid(IVec2::splat(LEN)),
+    };
+    let ks = (chunks.min.y..=chunks.max.y)
+        .flat_map(|y| (chunks.min.x..=chunks.max.x).map(move |x| ivec2(x, y)))
+        .collect::<Vec<_>>();
+    if !ks.iter().all(|k| map.in_world(*k)) {
+        return Err(invalid("image reaches past the periodic world"));
+    }
+    for k in ks {
+        if !map.contains(k) {
+            map.insert(k, Chunk::EMPTY)
+                .expect("checked to be in the world");
+        }
+    }
+
+    let channels = info.color_type.samples();
+    for (row, line) in buf
+        .chunks_exact(info.line_size)
+        .take(size.y as usize)
+        .enumerate()
+    {
+        for (x, pixel) in line
+            .chunks_exact(channels)
+            .take(size.x as usize)
+            .enumerate()
+        {
+            let color = match *pixel {
+                [l] => [l, l, l, u8::MAX],
+                [l, a] => [l, l, l, a],
+                [r, g, b] => [r, g, b, u8::MAX],
+                [r, g, b, a] => [r, g, b, a],
+                _ => unreachable!(),
+            };
+            if let Some(cell) = palette.cell::<E>(color) {
+                // rows go top to bottom
+                let pos = offset + ivec2(x as i32, size.y - 1 - row as i32);
+                map.set(pos, cell);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the cells in the inclusive `rect` as their material's colour, empty cells are transparent black
+pub fn export_png<E: CellEncoding>(
+    map: &ChunkMap<E>,
+    rect: IRect,
+    materials: &Materials,
+    w: impl Write,
+) -> io::Result<()> {
+    let size = rect.size() + 1;
+    let mut encoder = Encoder::new(w, size.x as u32, size.y as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(size.element_product() as usize * 4);
+    for y in (rect.min.y..=rect.max.y).rev() {
+        for x in rect.min.x..=rect.max.x {
+            let color = map
+                .get(ivec2(x, y))
+                .and_then(|c| materials.get(c.material()))
+                .map_or([0; 4], |m| m.color.to_srgba().to_u8_array());
+            data.extend_from_slice(&color);
+        }
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Runs one scene through the cli on task pools of different sizes, which only a new process can set up
+
+use bevy::prelude::*;
+use std::{fs::File, path::PathBuf, process::Command};
+
+use cellular_physics::{
+    chunk::Chunk,
+    chunk_map::ChunkMap,
+    encoding::Encoding32,
+    material::{MaterialId, Materials},
+    settings::SimulationSettings,
+};
+
+/// 4 by 4 chunks of sand and water crossing every chunk edge, saved for the cli
+fn scene() -> PathBuf {
+    let materials = Materials::default();
+    let mut map = ChunkMap::<Encoding32>::with_seed(7);
+    for y in 0..4 {
+        for x in 0..4 {
+            map.insert(ivec2(x, y), Chunk::EMPTY).unwrap();
+        }
+    }
+    for y in 40..220 {
+        for x in 10..246 {
+            let id = if (x / 24 + y / 24) % 2 == 0 {
+                MaterialId::SAND
+            } else {
+                MaterialId::WATER
+            };
+            map.set_dynamic(ivec2(x, y), id, &materials);
+        }
+    }
+
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("determinism.sav");
+    map.save(
+        &SimulationSettings::default(),
+        None,
+        File::create(&path).unwrap(),
+    )
+    .unwrap();
+    path
+}
+
+/// The world hash the cli ends with
+fn run(scene: &PathBuf, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_cellular_physics-cli"))
+        .arg(scene)
+        .args(["--steps", "40", "--sub-steps", "4"])
+        .args(args)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    stderr
+        .rsplit_once("world hash ")
+        .expect("the cli prints the world hash")
+        .1
+        .trim()
+        .to_owned()
+}
+
+#[test]
+fn same_hash_on_any_thread_count() {
+    let scene = scene();
+    for scheduler in [&["--deterministic"][..], &["--scheduler", "checkerboard"]] {
+        let hashes = ["1", "2", "3", "8"]
+            .map(|threads| run(&scene, &[scheduler, &["--threads", threads]].concat()));
+        assert!(
+            hashes.iter().all(|h| *h == hashes[0]),
+            "{scheduler:?}: {hashes:?}"
+        );
+    }
+}